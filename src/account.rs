@@ -0,0 +1,82 @@
+use actix_web::{
+    post,
+    web::{Data, Json},
+    HttpResponse,
+};
+use serde_json::json;
+
+use crate::auth::{AuthSettings, CurrentUser, Role, UserStore};
+use crate::two_factor;
+
+#[derive(serde::Deserialize)]
+pub struct CodeRequest {
+    pub code: String,
+}
+
+fn bad_request(details: String) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Two-factor update failed",
+            "details": details
+        }))
+}
+
+#[post("/account/2fa/enroll")]
+pub async fn enroll_two_factor(
+    users: Data<UserStore>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    match two_factor::begin_enrollment(&users, &user.username) {
+        Ok(enrollment) => Ok(HttpResponse::Ok()
+            .content_type("application/json; charset=utf-8")
+            .json(enrollment)),
+        Err(e) => Ok(bad_request(e)),
+    }
+}
+
+#[post("/account/2fa/confirm")]
+pub async fn confirm_two_factor(
+    users: Data<UserStore>,
+    body: Json<CodeRequest>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    match two_factor::confirm_enrollment(&users, &user.username, &body.code) {
+        Ok(recovery_codes) => {
+            log::info!("{} enabled two-factor authentication", user.username);
+            Ok(HttpResponse::Ok()
+                .content_type("application/json; charset=utf-8")
+                .json(json!({ "recovery_codes": recovery_codes })))
+        }
+        Err(e) => Ok(bad_request(e)),
+    }
+}
+
+#[post("/account/2fa/disable")]
+pub async fn disable_two_factor(
+    users: Data<UserStore>,
+    settings: Data<AuthSettings>,
+    body: Json<CodeRequest>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    if settings.require_admin_2fa && user.role >= Role::Admin {
+        return Ok(bad_request(
+            "Two-factor authentication is required for admins".to_string(),
+        ));
+    }
+
+    // Turning 2FA off needs a current code, so a hijacked session can't do it
+    match two_factor::verify(&users, &user.username, &body.code) {
+        Ok(true) => {}
+        Ok(false) => return Ok(bad_request("Invalid authentication code".to_string())),
+        Err(e) => return Ok(bad_request(e)),
+    }
+
+    match two_factor::disable(&users, &user.username) {
+        Ok(()) => {
+            log::info!("{} disabled two-factor authentication", user.username);
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(e) => Ok(bad_request(e)),
+    }
+}
@@ -1,18 +1,24 @@
 use actix_web::{
-    delete, get, patch, post,
-    web::{Data, Json, Path},
+    delete, get,
+    http::header::{ETag, EntityTag, IfMatch},
+    patch, post,
+    web::{Data, Header, Json, Path},
     HttpResponse,
 };
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, Utc};
 use serde_json::json;
-use std::{
-    cmp::Ordering,
-    collections::HashSet,
-    sync::{Arc, Mutex},
-};
+use std::cmp::Ordering;
 use thiserror::Error;
 use uuid::Uuid;
 
+use crate::audit::{AuditAction, AuditLog, AuditRecord};
+use crate::auth::{CurrentUser, Role};
+use crate::revisions::RevisionStore;
+use crate::roster::Roster;
+use crate::store::{StoreError, VersionedRemove, VersionedUpdate};
+use crate::trash;
+use crate::types::{EngagementRepo, TrashRepo};
+
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
 pub enum Language {
     English,
@@ -23,6 +29,23 @@ pub enum Language {
     German,
 }
 
+impl Language {
+    pub const ALL: [Language; 6] = [
+        Language::English,
+        Language::Spanish,
+        Language::French,
+        Language::Italian,
+        Language::Portuguese,
+        Language::German,
+    ];
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
 pub enum Status {
     Planning,
@@ -43,14 +66,19 @@ impl std::fmt::Display for Status {
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Engagement {
     pub id: Uuid,
-    pub instructor: String,
-    pub host: String,
+    pub instructor_id: Uuid,
+    pub host_id: Uuid,
     pub date: String,
     pub language: Language,
     pub title: String,
     pub part: usize,
     pub num_parts: usize,
     pub status: Status,
+    /// Incremented on every edit, so a client can tell whether the engagement
+    /// changed since it was read. Engagements stored before versioning was
+    /// added are at version 0.
+    #[serde(default)]
+    pub version: u64,
 }
 
 #[derive(Error, Debug)]
@@ -67,7 +95,7 @@ impl Engagement {
             .map_err(|e| EngagementError::DateParseError(self.date.clone(), e))
     }
 
-    fn validate(&self) -> Result<(), String> {
+    pub fn validate(&self) -> Result<(), String> {
         NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").map_err(|_| {
             format!(
                 "Invalid date format: {}. Expected format: YYYY-MM-DD",
@@ -97,18 +125,32 @@ impl Engagement {
 
         Self {
             id: self.id, 
-            instructor: ammonia::clean(&self.instructor),
-            host: ammonia::clean(&self.host),
+            instructor_id: self.instructor_id,
+            host_id: self.host_id,
             date: ammonia::clean(&self.date),
             language: self.language.clone(),
             title: ammonia::clean(&self.title),
-            part: self.part.clone(),
-            num_parts: self.num_parts.clone(),
+            part: self.part,
+            num_parts: self.num_parts,
             status: self.status.clone(),
+            version: self.version,
         }
     }
 }
 
+/// Text as stored, with the HTML entities `ammonia::clean` wrote decoded,
+/// for output that isn't HTML such as CSV files and calendars.
+pub fn plain_text(stored: &str) -> String {
+    stored
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", "\u{a0}")
+        // Last, so an escaped entity like `&amp;lt;` stays `&lt;`
+        .replace("&amp;", "&")
+}
+
 pub fn compare_engagement_dates(
     a: &Engagement,
     b: &Engagement,
@@ -116,11 +158,11 @@ pub fn compare_engagement_dates(
     match (a.parse_date(), b.parse_date()) {
         (Ok(date_a), Ok(date_b)) => Ok(date_a.cmp(&date_b)),
         (Err(_), _) => Err(EngagementError::InvalidDateFormat(
-            a.instructor.clone(),
+            a.title.clone(),
             a.date.clone(),
         )),
         (_, Err(_)) => Err(EngagementError::InvalidDateFormat(
-            b.instructor.clone(),
+            b.title.clone(),
             b.date.clone(),
         )),
     }
@@ -140,10 +182,32 @@ impl PartialEq for Engagement {
 
 impl Eq for Engagement {}
 
+/// A deleted engagement, kept in the trash until it is restored or purged.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct TrashedEngagement {
+    pub engagement: Engagement,
+    pub deleted_at: DateTime<Utc>,
+    pub deleted_by: String,
+}
+
+impl std::hash::Hash for TrashedEngagement {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.engagement.hash(state);
+    }
+}
+
+impl PartialEq for TrashedEngagement {
+    fn eq(&self, other: &Self) -> bool {
+        self.engagement == other.engagement
+    }
+}
+
+impl Eq for TrashedEngagement {}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct NewEngagement {
-    pub instructor: String,
-    pub host: String,
+    pub instructor_id: Uuid,
+    pub host_id: Uuid,
     pub date: String,
     pub language: Language,
     pub title: String,
@@ -153,7 +217,7 @@ pub struct NewEngagement {
 }
 
 impl NewEngagement {
-    fn validate(&self) -> Result<(), String> {
+    pub fn validate(&self) -> Result<(), String> {
         NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").map_err(|_| {
             format!(
                 "Invalid date format: {}. Expected format: YYYY-MM-DD",
@@ -178,13 +242,112 @@ impl NewEngagement {
 
         Ok(())
     }
+
+    /// Builds the engagement to store, with a fresh id and sanitized text.
+    pub fn into_engagement(self) -> Engagement {
+        Engagement {
+            id: Uuid::new_v4(),
+            instructor_id: self.instructor_id,
+            host_id: self.host_id,
+            date: ammonia::clean(&self.date),
+            language: self.language,
+            title: ammonia::clean(&self.title),
+            part: self.part,
+            num_parts: self.num_parts,
+            status: self.status,
+            version: 1,
+        }
+    }
+}
+
+/// An engagement as sent to be edited or deleted. The version the client
+/// last read is kept apart from the engagement, whose own version defaults
+/// to 0 for records stored before versioning, so a body without one can be
+/// told apart from a body for one of those records.
+#[derive(serde::Deserialize)]
+pub struct EngagementChange {
+    #[serde(flatten)]
+    pub engagement: Engagement,
+    pub version: Option<u64>,
+}
+
+pub fn find_eng(repo: &EngagementRepo, id: Uuid) -> Result<Option<Engagement>, StoreError> {
+    Ok(repo.list()?.into_iter().find(|eng| eng.id == id))
+}
+
+/// The version of an engagement a client last read, from its `If-Match`
+/// header when one is sent and from the `version` in the body otherwise.
+/// `If-Match: *` accepts whatever version is currently stored. `None` means
+/// the client gave neither.
+fn expected_version(
+    if_match: Option<&IfMatch>,
+    body_version: Option<u64>,
+    current: &Engagement,
+) -> Result<Option<u64>, String> {
+    match if_match {
+        Some(IfMatch::Any) => Ok(Some(current.version)),
+        Some(IfMatch::Items(tags)) => match tags.as_slice() {
+            // An absent header parses as an empty list
+            [] => Ok(body_version),
+            [tag] => tag
+                .tag()
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("'{}' is not an engagement version", tag.tag())),
+            _ => Err("If-Match must name a single engagement version".to_string()),
+        },
+        None => Ok(body_version),
+    }
+}
+
+fn invalid_if_match(details: String) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Invalid If-Match header",
+            "details": details
+        }))
+}
+
+/// Response for a change that doesn't say which version it was based on,
+/// so it can't be checked against changes made since.
+fn version_required() -> HttpResponse {
+    HttpResponse::PreconditionRequired()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Version required",
+            "details": "Send the engagement version you last read in an If-Match header \
+                        or as the version in the body"
+        }))
+}
+
+/// Response for a change based on an outdated copy of an engagement, with
+/// the stored copy so the client can reapply its change on top of it.
+pub fn version_conflict(current: &Engagement) -> HttpResponse {
+    HttpResponse::Conflict()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Version conflict",
+            "details": format!(
+                "This engagement was changed by someone else and is now at version {}",
+                current.version
+            ),
+            "current": current
+        }))
 }
 
 #[post("/engs")]
 pub async fn add_eng(
-    repo: Data<Arc<Mutex<HashSet<Engagement>>>>,
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    revisions: Data<RevisionStore>,
+    audit: Data<AuditLog>,
     body: Json<NewEngagement>,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+    user.require_language(&body.language)?;
+
     if let Err(validation_error) = body.validate() {
         return Ok(HttpResponse::BadRequest()
             .content_type("application/json")
@@ -194,57 +357,62 @@ pub async fn add_eng(
             })));
     }
 
-    match repo.lock() {
-        Ok(mut repo) => {
-            let new_eng = Engagement {
-                id: Uuid::new_v4(),
-                instructor: ammonia::clean(&body.instructor),
-                host: ammonia::clean(&body.host),
-                date: ammonia::clean(&body.date),
-                language: body.language.clone(),
-                title: ammonia::clean(&body.title),
-                part: body.part.clone(),
-                num_parts: body.num_parts.clone(),
-                status: body.status.clone(),
-            };
-            repo.insert(new_eng);
-
-            Ok(HttpResponse::Created().finish())
-        }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+    let engagement = body.into_inner().into_engagement();
+    if let Err(roster_error) = roster.check(&engagement, None)? {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Validation failed",
+                "details": roster_error
+            })));
     }
+
+    repo.insert(engagement.clone())?;
+    revisions.record_created(&engagement, &user.username)?;
+    audit.record(
+        AuditRecord::new(&user.username, AuditAction::AddEngagement, engagement.id)
+            .after(&engagement),
+    )?;
+
+    Ok(HttpResponse::Created().finish())
 }
 
 #[get("engs/{lang}")]
 pub async fn get_engs(
-    repo: Data<Arc<Mutex<HashSet<Engagement>>>>,
+    repo: Data<EngagementRepo>,
     lang: Path<Language>,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    match repo.lock() {
-        Ok(repo) => {
-            let engagements: Vec<Engagement> = repo
-                .iter()
-                .filter(|x| x.language == *lang)
-                .cloned()
-                .collect();
+    user.require(Role::Viewer)?;
+    user.require_language(&lang)?;
 
-            Ok(HttpResponse::Ok()
-                .content_type("application/json; charset=utf-8")
-                .json(engagements))
-        }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
-    }
+    let engagements: Vec<Engagement> = repo
+        .list()?
+        .into_iter()
+        .filter(|x| x.language == *lang)
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(engagements))
 }
 
 #[patch("/engs")]
 pub async fn edit_eng(
-    repo: Data<Arc<Mutex<HashSet<Engagement>>>>,
-    body: Json<Engagement>,
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    revisions: Data<RevisionStore>,
+    audit: Data<AuditLog>,
+    body: Json<EngagementChange>,
+    if_match: Option<Header<IfMatch>>,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let EngagementChange {
+        engagement: body,
+        version: body_version,
+    } = body.into_inner();
     if let Err(validation_error) = body.validate() {
         return Ok(HttpResponse::BadRequest()
             .content_type("application/json")
@@ -254,40 +422,219 @@ pub async fn edit_eng(
             })));
     }
 
-    match repo.lock() {
-        Ok(mut repo) => {
-            let target_eng = body.into_inner().clean();
-            if repo.contains(&target_eng) {
-                repo.remove(&target_eng);
-                repo.insert(target_eng.clone());
-                Ok(HttpResponse::Ok().finish())
-            } else {
-                Ok(HttpResponse::NotFound().finish())
-            }
+    // Both the stored engagement and its edited version have to be in one of
+    // the user's languages, so engagements can't be moved between teams
+    let Some(current) = find_eng(&repo, body.id)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    user.require_language(&current.language)?;
+    user.require_language(&body.language)?;
+
+    let expected = match expected_version(if_match.as_deref(), body_version, &current) {
+        Ok(Some(version)) => version,
+        Ok(None) => return Ok(version_required()),
+        Err(details) => return Ok(invalid_if_match(details)),
+    };
+
+    let target_eng = body.clean();
+    if let Err(roster_error) = roster.check(&target_eng, Some(&current))? {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Validation failed",
+                "details": roster_error
+            })));
+    }
+
+    match repo.update(target_eng, expected)? {
+        VersionedUpdate::Updated(updated) => {
+            revisions.record_edit(&current, &updated, &user.username, None)?;
+            audit.record(
+                AuditRecord::new(&user.username, AuditAction::EditEngagement, updated.id)
+                    .before(&current)
+                    .after(&updated),
+            )?;
+            Ok(HttpResponse::Ok()
+                .insert_header(ETag(EntityTag::new_strong(updated.version.to_string())))
+                .content_type("application/json; charset=utf-8")
+                .json(updated))
         }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+        VersionedUpdate::Conflict(stored) => Ok(version_conflict(&stored)),
+        VersionedUpdate::NotFound => Ok(HttpResponse::NotFound().finish()),
     }
 }
 
 #[delete("/engs")]
 pub async fn delete_eng(
-    repo: Data<Arc<Mutex<HashSet<Engagement>>>>,
-    body: Json<Engagement>,
+    repo: Data<EngagementRepo>,
+    trash: Data<TrashRepo>,
+    audit: Data<AuditLog>,
+    body: Json<EngagementChange>,
+    if_match: Option<Header<IfMatch>>,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    match repo.lock() {
-        Ok(mut repo) => {
-            let target_eng = body.into_inner();
-            if repo.contains(&target_eng) {
-                repo.remove(&target_eng);
-                Ok(HttpResponse::Ok().finish())
-            } else {
-                Ok(HttpResponse::NotFound().finish())
+    user.require(Role::Scheduler)?;
+
+    let Some(target_eng) = find_eng(&repo, body.engagement.id)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    user.require_language(&target_eng.language)?;
+
+    match expected_version(if_match.as_deref(), body.version, &target_eng) {
+        Ok(Some(version)) if version != target_eng.version => {
+            return Ok(version_conflict(&target_eng));
+        }
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(version_required()),
+        Err(details) => return Ok(invalid_if_match(details)),
+    }
+
+    // Deleted engagements go to the trash, where they can be restored until
+    // they are purged. The version is checked again as it is removed, in case
+    // an edit landed since it was read above.
+    match trash::trash_engagement(
+        &repo,
+        &trash,
+        target_eng.id,
+        target_eng.version,
+        &user.username,
+    )? {
+        VersionedRemove::Removed(removed) => {
+            audit.record(
+                AuditRecord::new(&user.username, AuditAction::DeleteEngagement, removed.id)
+                    .before(&removed),
+            )?;
+            Ok(HttpResponse::Ok().finish())
+        }
+        VersionedRemove::Conflict(current) => Ok(version_conflict(&current)),
+        VersionedRemove::NotFound => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::User;
+    use crate::store::LocalStore;
+    use crate::two_factor::TwoFactor;
+    use actix_web::{
+        dev::Service,
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        App, HttpMessage,
+    };
+    use std::sync::Arc;
+
+    fn engagement(version: u64) -> Engagement {
+        Engagement {
+            version,
+            ..NewEngagement {
+                instructor_id: Uuid::new_v4(),
+                host_id: Uuid::new_v4(),
+                date: "2024-05-01".to_string(),
+                language: Language::English,
+                title: "Intro".to_string(),
+                part: 1,
+                num_parts: 1,
+                status: Status::Planning,
             }
+            .into_engagement()
         }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+    }
+
+    fn tags(tags: &[&str]) -> IfMatch {
+        IfMatch::Items(
+            tags.iter()
+                .map(|t| EntityTag::new_strong(t.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn if_match_takes_precedence_over_the_body() {
+        let current = engagement(5);
+
+        assert_eq!(expected_version(None, Some(2), &current), Ok(Some(2)));
+        assert_eq!(
+            expected_version(Some(&tags(&[])), Some(2), &current),
+            Ok(Some(2))
+        );
+        assert_eq!(
+            expected_version(Some(&tags(&["3"])), Some(2), &current),
+            Ok(Some(3))
+        );
+        assert_eq!(
+            expected_version(Some(&IfMatch::Any), Some(2), &current),
+            Ok(Some(5))
+        );
+        assert_eq!(expected_version(None, None, &current), Ok(None));
+        assert_eq!(
+            expected_version(Some(&tags(&["3"])), None, &current),
+            Ok(Some(3))
+        );
+    }
+
+    #[test]
+    fn bad_if_match_headers_are_rejected() {
+        let current = engagement(5);
+
+        assert!(expected_version(Some(&tags(&["v3"])), Some(2), &current).is_err());
+        assert!(expected_version(Some(&tags(&["3", "4"])), Some(2), &current).is_err());
+    }
+
+    #[test]
+    fn bodies_without_a_version_are_told_apart() {
+        let mut body = serde_json::to_value(engagement(0)).unwrap();
+        let change: EngagementChange = serde_json::from_value(body.clone()).unwrap();
+        assert_eq!(change.version, Some(0));
+
+        body.as_object_mut().unwrap().remove("version");
+        let change: EngagementChange = serde_json::from_value(body).unwrap();
+        assert_eq!(change.version, None);
+        assert_eq!(change.engagement.version, 0);
+    }
+
+    #[actix_web::test]
+    async fn legacy_records_need_a_stated_version() {
+        let store = Arc::new(LocalStore::in_memory());
+        let repo = EngagementRepo::new(store.clone());
+        let legacy = engagement(0);
+        repo.insert(legacy.clone()).unwrap();
+        let path = std::env::temp_dir().join(format!("api-test-{}.jsonl", Uuid::new_v4()));
+        let scheduler = User {
+            username: "ann".to_string(),
+            password_hash: String::new(),
+            disabled: false,
+            created_at: Utc::now(),
+            role: Role::Scheduler,
+            languages: Vec::new(),
+            two_factor: TwoFactor::default(),
+        };
+
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(repo.clone()))
+                .app_data(Data::new(TrashRepo::new(store)))
+                .app_data(Data::new(AuditLog::open(&path).unwrap()))
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(scheduler.clone());
+                    srv.call(req)
+                })
+                .service(delete_eng),
+        )
+        .await;
+        let mut body = serde_json::to_value(&legacy).unwrap();
+        body.as_object_mut().unwrap().remove("version");
+
+        let req = TestRequest::delete().uri("/engs").set_json(&body).to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(repo.list().unwrap(), vec![legacy.clone()]);
+
+        body["version"] = json!(0);
+        let req = TestRequest::delete().uri("/engs").set_json(&body).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+        assert!(repo.list().unwrap().is_empty());
+        let _ = std::fs::remove_file(path);
     }
 }
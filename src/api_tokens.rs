@@ -0,0 +1,306 @@
+use actix_web::{dev::ServiceRequest, http::header};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fs::File, io::BufReader, path::PathBuf, sync::Mutex};
+
+use crate::api::Language;
+use crate::auth::{Role, User};
+use crate::store::{persist, StoreError};
+use crate::two_factor::TwoFactor;
+
+// Prefix that makes leaked tokens easy to recognise, e.g. by secret scanners
+const TOKEN_PREFIX: &str = "apt_";
+const TOKEN_BYTES: usize = 32;
+// How far behind the token file's last-used times may fall; they are kept
+// current in memory, but writing the file on every request is too costly
+const LAST_USED_WRITE_INTERVAL_MINS: i64 = 10;
+
+/// The engagement API, the only routes bearer tokens are accepted on. Every
+/// other route is for people and needs a logged-in session.
+pub const TOKEN_PATH: &str = "/engs";
+
+/// Whether `path` is `TOKEN_PATH` or one of the routes beneath it, such as
+/// the per-language listing.
+pub fn accepts_tokens(path: &str) -> bool {
+    path.strip_prefix(TOKEN_PATH)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// What a token's holder may do with the engagement API.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenScope {
+    /// Can only list engagements, e.g. for a website build
+    ReadOnly,
+    /// Can also create, edit and delete engagements
+    ReadWrite,
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 2] = [TokenScope::ReadOnly, TokenScope::ReadWrite];
+
+    /// The user role a token with this scope acts with. Tokens are never
+    /// admins, so they can't manage users, backups or other tokens.
+    fn role(self) -> Role {
+        match self {
+            TokenScope::ReadOnly => Role::Viewer,
+            TokenScope::ReadWrite => Role::Scheduler,
+        }
+    }
+}
+
+impl std::fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A named bearer token for machine clients. Only a SHA-256 hash of the
+/// token is kept; the token itself is shown once, when it's created.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub token_hash: String,
+    pub scope: TokenScope,
+    /// Languages the token may access. An empty list allows all of them.
+    pub languages: Vec<Language>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    /// The account requests made with this token act as, so handlers can
+    /// check roles and languages the same way they do for people.
+    pub fn as_user(&self) -> User {
+        User {
+            username: format!("token:{}", self.name),
+            password_hash: String::new(),
+            disabled: false,
+            created_at: self.created_at,
+            role: self.scope.role(),
+            languages: self.languages.clone(),
+            two_factor: TwoFactor::default(),
+        }
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the token from an `Authorization: Bearer` header, if there is one.
+/// Callers must still check the request's path with `accepts_tokens`.
+pub fn bearer_token(req: &ServiceRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// API tokens keyed by id, persisted to a JSON file on every change.
+pub struct ApiTokenStore {
+    tokens: Mutex<HashMap<String, ApiToken>>,
+    path: PathBuf,
+    /// When last-used times were last written out, whichever token was used.
+    /// Only taken while holding `tokens`.
+    last_used_written: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl ApiTokenStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let path = path.into();
+        let tokens = if path.exists() {
+            serde_json::from_reader(BufReader::new(File::open(&path)?))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            tokens: Mutex::new(tokens),
+            path,
+            last_used_written: Mutex::new(None),
+        })
+    }
+
+    /// Lists every token, newest first.
+    pub fn list(&self) -> Result<Vec<ApiToken>, StoreError> {
+        let tokens = self.tokens.lock().map_err(|_| StoreError::Lock)?;
+        let mut tokens: Vec<ApiToken> = tokens.values().cloned().collect();
+        tokens.sort_by_key(|t| std::cmp::Reverse(t.created_at));
+        Ok(tokens)
+    }
+
+    /// Applies `f` to the token map and persists the result. Nothing is
+    /// written if `f` returns an error.
+    fn modify<R>(
+        &self,
+        f: impl FnOnce(&mut HashMap<String, ApiToken>) -> Result<R, String>,
+    ) -> Result<Result<R, String>, StoreError> {
+        let mut tokens = self.tokens.lock().map_err(|_| StoreError::Lock)?;
+        let mut next = tokens.clone();
+        let result = match f(&mut next) {
+            Ok(result) => result,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        persist(&self.path, &next)?;
+        *tokens = next;
+        Ok(Ok(result))
+    }
+}
+
+/// Creates a token, returning its record and the token itself. The token
+/// can't be recovered later, so it has to be handed to the caller now.
+pub fn create_token(
+    tokens: &ApiTokenStore,
+    name: &str,
+    scope: TokenScope,
+    languages: Vec<Language>,
+    created_by: &str,
+) -> Result<(ApiToken, String), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Token name must not be empty".to_string());
+    }
+
+    let token = format!("{}{}", TOKEN_PREFIX, random_hex(TOKEN_BYTES));
+    let record = ApiToken {
+        id: random_hex(8),
+        name: name.to_string(),
+        token_hash: hash_token(&token),
+        scope,
+        languages,
+        created_by: created_by.to_string(),
+        created_at: Utc::now(),
+        last_used: None,
+    };
+
+    tokens
+        .modify(|tokens| {
+            if tokens.values().any(|t| t.name == record.name) {
+                return Err(format!("A token named '{}' already exists", record.name));
+            }
+            tokens.insert(record.id.clone(), record.clone());
+            Ok(())
+        })
+        .map_err(|e| e.to_string())??;
+
+    Ok((record, token))
+}
+
+/// Deletes the token with `id`, returning false if there wasn't one.
+pub fn revoke_token(tokens: &ApiTokenStore, id: &str) -> Result<bool, StoreError> {
+    tokens
+        .modify(|tokens| Ok(tokens.remove(id).is_some()))
+        .map(|result| result.unwrap_or(false))
+}
+
+/// Looks up the token a request presented, recording when it was used.
+pub fn authenticate(tokens: &ApiTokenStore, token: &str) -> Result<Option<ApiToken>, StoreError> {
+    let hashed = hash_token(token);
+    let mut map = tokens.tokens.lock().map_err(|_| StoreError::Lock)?;
+    let Some(found) = map.values_mut().find(|t| t.token_hash == hashed) else {
+        return Ok(None);
+    };
+
+    let now = Utc::now();
+    found.last_used = Some(now);
+    let found = found.clone();
+
+    // Measured from the last write rather than the last use, so tokens in
+    // steady use still get their times written
+    let mut last_written = tokens.last_used_written.lock().map_err(|_| StoreError::Lock)?;
+    let write_due = last_written
+        .is_none_or(|t| now - t >= Duration::minutes(LAST_USED_WRITE_INTERVAL_MINS));
+    if write_due {
+        *last_written = Some(now);
+        // Only the last-used time is at stake, so the request can go ahead
+        if let Err(e) = persist(&tokens.path, &*map) {
+            log::warn!("Failed to record API token use: {}", e);
+        }
+    }
+    Ok(Some(found))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn store() -> (ApiTokenStore, PathBuf) {
+        let path = std::env::temp_dir().join(format!("tokens-test-{}.json", Uuid::new_v4()));
+        (ApiTokenStore::open(&path).unwrap(), path)
+    }
+
+    fn saved(path: &PathBuf) -> HashMap<String, ApiToken> {
+        serde_json::from_reader(BufReader::new(File::open(path).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn only_the_hash_is_stored() {
+        let (tokens, path) = store();
+        let (record, token) =
+            create_token(&tokens, "site", TokenScope::ReadOnly, Vec::new(), "admin").unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_ne!(record.token_hash, token);
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(&token));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn authenticate_finds_live_tokens_only() {
+        let (tokens, path) = store();
+        let (record, token) =
+            create_token(&tokens, "site", TokenScope::ReadWrite, Vec::new(), "admin").unwrap();
+        assert_eq!(authenticate(&tokens, &token).unwrap().unwrap().id, record.id);
+        assert!(authenticate(&tokens, "apt_unknown").unwrap().is_none());
+
+        assert!(revoke_token(&tokens, &record.id).unwrap());
+        assert!(authenticate(&tokens, &token).unwrap().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn last_used_is_written_at_most_once_per_interval() {
+        let (tokens, path) = store();
+        let (record, token) =
+            create_token(&tokens, "site", TokenScope::ReadOnly, Vec::new(), "admin").unwrap();
+
+        let first = authenticate(&tokens, &token).unwrap().unwrap();
+        let written = saved(&path)[&record.id].last_used;
+        assert_eq!(written, first.last_used);
+
+        // Later uses are tracked in memory without rewriting the file
+        let second = authenticate(&tokens, &token).unwrap().unwrap();
+        assert!(second.last_used >= first.last_used);
+        assert_eq!(saved(&path)[&record.id].last_used, written);
+        assert_eq!(tokens.list().unwrap()[0].last_used, second.last_used);
+
+        // Once the interval has passed since the last write, the next use is
+        // written even though the token was in use all along
+        *tokens.last_used_written.lock().unwrap() =
+            Some(Utc::now() - Duration::minutes(LAST_USED_WRITE_INTERVAL_MINS));
+        let third = authenticate(&tokens, &token).unwrap().unwrap();
+        assert_eq!(saved(&path)[&record.id].last_used, third.last_used);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let (tokens, path) = store();
+        create_token(&tokens, "site", TokenScope::ReadOnly, Vec::new(), "admin").unwrap();
+        assert!(create_token(&tokens, " site ", TokenScope::ReadOnly, Vec::new(), "a").is_err());
+        assert!(create_token(&tokens, "  ", TokenScope::ReadOnly, Vec::new(), "a").is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
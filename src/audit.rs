@@ -0,0 +1,325 @@
+use actix_web::{
+    get,
+    web::{Data, Query},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{de::IntoDeserializer, Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+use crate::auth::{CurrentUser, Role};
+use crate::store::StoreError;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AuditAction {
+    AddEngagement,
+    EditEngagement,
+    RevertEngagement,
+    DeleteEngagement,
+    RestoreEngagement,
+    PurgeEngagement,
+    AddInstructor,
+    EditInstructor,
+    DeleteInstructor,
+    AddHost,
+    EditHost,
+    DeleteHost,
+    RestoreBackup,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 13] = [
+        AuditAction::AddEngagement,
+        AuditAction::EditEngagement,
+        AuditAction::RevertEngagement,
+        AuditAction::DeleteEngagement,
+        AuditAction::RestoreEngagement,
+        AuditAction::PurgeEngagement,
+        AuditAction::AddInstructor,
+        AuditAction::EditInstructor,
+        AuditAction::DeleteInstructor,
+        AuditAction::AddHost,
+        AuditAction::EditHost,
+        AuditAction::DeleteHost,
+        AuditAction::RestoreBackup,
+    ];
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// One change to the schedule: who made it, when, and the affected record
+/// as it was before and after.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct AuditRecord {
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub user: String,
+    pub action: AuditAction,
+    /// Engagement id, roster name or backup name the change applies to
+    pub target: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+impl AuditRecord {
+    pub fn new(user: &str, action: AuditAction, target: impl ToString) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            user: user.to_string(),
+            action,
+            target: target.to_string(),
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn before(mut self, value: &impl Serialize) -> Self {
+        self.before = serde_json::to_value(value).ok();
+        self
+    }
+
+    pub fn after(mut self, value: &impl Serialize) -> Self {
+        self.after = serde_json::to_value(value).ok();
+        self
+    }
+}
+
+/// Append-only record of every change made through the app, stored as one
+/// JSON entry per line. Unlike the journal it is never truncated.
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, record: AuditRecord) -> Result<(), StoreError> {
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().map_err(|_| StoreError::Lock)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Every record, oldest first.
+    pub fn entries(&self) -> Result<Vec<AuditRecord>, StoreError> {
+        let _file = self.file.lock().map_err(|_| StoreError::Lock)?;
+        read_records(&self.path)
+    }
+
+    /// Adds restored records that aren't in the log yet. History is only
+    /// ever added to, so a restore can't erase changes made since the backup.
+    pub fn merge(&self, records: Vec<AuditRecord>) -> Result<usize, StoreError> {
+        let mut file = self.file.lock().map_err(|_| StoreError::Lock)?;
+        let mut merged: HashMap<Uuid, AuditRecord> = read_records(&self.path)?
+            .into_iter()
+            .map(|r| (r.id, r))
+            .collect();
+        let before = merged.len();
+        for record in records {
+            merged.entry(record.id).or_insert(record);
+        }
+        let added = merged.len() - before;
+        if added == 0 {
+            return Ok(0);
+        }
+
+        let mut merged: Vec<AuditRecord> = merged.into_values().collect();
+        merged.sort_by_key(|r| r.timestamp);
+
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            for record in &merged {
+                serde_json::to_writer(&mut tmp, record)?;
+                tmp.write_all(b"\n")?;
+            }
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+
+        *file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(added)
+    }
+}
+
+fn read_records(path: &Path) -> Result<Vec<AuditRecord>, StoreError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            // A crash mid-append can leave a partial final line behind
+            Err(e) => log::warn!("Skipping unreadable audit record: {}", e),
+        }
+    }
+    Ok(records)
+}
+
+/// Filter forms submit an empty value for "any action".
+fn action_filter<'de, D: Deserializer<'de>>(d: D) -> Result<Option<AuditAction>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .filter(|action| !action.is_empty())
+        .map(|action| AuditAction::deserialize(action.into_deserializer()))
+        .transpose()
+}
+
+#[derive(serde::Deserialize)]
+pub struct AuditQuery {
+    pub user: Option<String>,
+    #[serde(default, deserialize_with = "action_filter")]
+    pub action: Option<AuditAction>,
+    /// Matched against the target and the before/after JSON
+    pub q: Option<String>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.user
+            .as_ref()
+            .filter(|u| !u.is_empty())
+            .is_none_or(|u| record.user == *u)
+            && self.action.is_none_or(|a| record.action == a)
+            && self
+                .q
+                .as_ref()
+                .map(|q| q.trim().to_lowercase())
+                .filter(|q| !q.is_empty())
+                .is_none_or(|q| {
+                    record.target.to_lowercase().contains(&q)
+                        || [&record.before, &record.after]
+                            .into_iter()
+                            .flatten()
+                            .any(|v| v.to_string().to_lowercase().contains(&q))
+                })
+    }
+}
+
+/// Matching audit records, newest first.
+pub fn search(log: &AuditLog, query: &AuditQuery) -> Result<Vec<AuditRecord>, StoreError> {
+    let mut records: Vec<AuditRecord> = log
+        .entries()?
+        .into_iter()
+        .filter(|r| query.matches(r))
+        .collect();
+    records.reverse();
+    Ok(records)
+}
+
+#[get("/audit")]
+pub async fn get_audit(
+    log: Data<AuditLog>,
+    query: Query<AuditQuery>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(search(&log, &query)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn log() -> (AuditLog, PathBuf) {
+        let path = std::env::temp_dir().join(format!("audit-test-{}.jsonl", Uuid::new_v4()));
+        (AuditLog::open(&path).unwrap(), path)
+    }
+
+    fn query(query: &str) -> AuditQuery {
+        Query::<AuditQuery>::from_query(query).unwrap().into_inner()
+    }
+
+    #[test]
+    fn partial_lines_are_skipped() {
+        let (log, path) = log();
+        log.record(AuditRecord::new("ann", AuditAction::AddHost, "Hall"))
+            .unwrap();
+        // As a crash partway through an append would leave it
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"id\":")
+            .unwrap();
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].target, "Hall");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn merging_only_adds_missing_records() {
+        let (log, path) = log();
+        let older = AuditRecord {
+            timestamp: Utc::now() - chrono::Duration::days(1),
+            ..AuditRecord::new("ann", AuditAction::AddHost, "Hall")
+        };
+        let current = AuditRecord::new("bob", AuditAction::EditHost, "Hall");
+        log.record(current.clone()).unwrap();
+
+        assert_eq!(log.merge(vec![older.clone(), current.clone()]).unwrap(), 1);
+        assert_eq!(log.merge(vec![older.clone()]).unwrap(), 0);
+        let ids: Vec<Uuid> = log.entries().unwrap().iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![older.id, current.id]);
+
+        // Still appends after the rewrite
+        log.record(AuditRecord::new("ann", AuditAction::DeleteHost, "Hall"))
+            .unwrap();
+        assert_eq!(log.entries().unwrap().len(), 3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn queries_match_on_every_field_given() {
+        let record = AuditRecord::new("ann", AuditAction::EditEngagement, Uuid::new_v4())
+            .before(&json!({ "title": "Intro" }))
+            .after(&json!({ "title": "Advanced" }));
+
+        assert!(query("user=&action=&q=").matches(&record));
+        assert!(query("user=ann&action=EditEngagement").matches(&record));
+        assert!(query("q=ADVANCED").matches(&record));
+        assert!(!query("user=bob").matches(&record));
+        assert!(!query("action=AddEngagement").matches(&record));
+        assert!(!query("q=expert").matches(&record));
+        assert!(Query::<AuditQuery>::from_query("action=Nope").is_err());
+    }
+}
@@ -1,19 +1,203 @@
 use actix_identity::Identity;
+use actix_session::Session;
 use actix_web::{
-    error::{ErrorInternalServerError, ErrorUnauthorized},
-    get, post,
+    dev::Payload,
+    error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+    get,
+    http::StatusCode,
+    post,
     web::{Data, Form},
-    Error, HttpMessage, HttpRequest, HttpResponse,
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
 };
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 use askama::Template;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    collections::HashMap,
+    fs::File,
+    future::{ready, Ready},
+    io::BufReader,
+    ops::Deref,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::api::Language;
+use crate::csrf::{self, CsrfToken};
+use crate::login_throttle::{LoginThrottle, ThrottleKey};
+use crate::store::{persist, StoreError};
+use crate::two_factor::{self, TwoFactor};
+
+/// What a user may do. Roles are ordered, so each one includes everything
+/// the roles below it can do.
+#[derive(
+    serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum Role {
+    /// Read-only access to engagements
+    Viewer,
+    /// Can create, edit and delete engagements and add to the roster
+    Scheduler,
+    /// Full access, including roster deletions, backups and user management
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Login policy read from the environment at startup.
+pub struct AuthSettings {
+    /// Admins without two-factor authentication are sent to enroll before
+    /// they can use anything else
+    pub require_admin_2fa: bool,
+}
+
+impl AuthSettings {
+    pub fn from_env() -> Self {
+        Self {
+            require_admin_2fa: std::env::var("REQUIRE_ADMIN_2FA")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+        }
+    }
+}
+
+// Accounts saved before roles existed had unrestricted access
+fn legacy_role() -> Role {
+    Role::Admin
+}
 
-pub struct UserStore(pub Mutex<HashMap<String, String>>);
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct User {
+    pub username: String,
+    pub password_hash: String,
+    pub disabled: bool,
+    pub created_at: DateTime<Utc>,
+    #[serde(default = "legacy_role")]
+    pub role: Role,
+    /// Language teams this user works with. An empty list leaves the user
+    /// unscoped, as every account was before languages could be assigned.
+    #[serde(default)]
+    pub languages: Vec<Language>,
+    #[serde(default)]
+    pub two_factor: TwoFactor,
+}
+
+impl User {
+    /// Admins can always reach every language; everyone else is limited to
+    /// their assigned languages, if they have any.
+    pub fn can_access(&self, language: &Language) -> bool {
+        self.role >= Role::Admin || self.languages.is_empty() || self.languages.contains(language)
+    }
+}
+
+/// The signed-in user, as loaded by `AuthMiddleware`. Handlers take this to
+/// check the user's role before acting.
+pub struct CurrentUser(pub User);
+
+impl CurrentUser {
+    pub fn require(&self, role: Role) -> Result<(), Error> {
+        if self.0.role >= role {
+            Ok(())
+        } else {
+            Err(ErrorForbidden(format!("This action requires the {} role", role)))
+        }
+    }
+
+    pub fn require_language(&self, language: &Language) -> Result<(), Error> {
+        if self.0.can_access(language) {
+            Ok(())
+        } else {
+            Err(ErrorForbidden(format!("You are not assigned to {}", language)))
+        }
+    }
+}
+
+impl Deref for CurrentUser {
+    type Target = User;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for CurrentUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<User>()
+                .cloned()
+                .map(CurrentUser)
+                .ok_or_else(|| ErrorUnauthorized("Not logged in")),
+        )
+    }
+}
+
+/// User accounts keyed by username, persisted to a JSON file on every change.
+pub struct UserStore {
+    users: Mutex<HashMap<String, User>>,
+    path: PathBuf,
+}
+
+impl UserStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let path = path.into();
+        let users = if path.exists() {
+            serde_json::from_reader(BufReader::new(File::open(&path)?))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            users: Mutex::new(users),
+            path,
+        })
+    }
+
+    pub fn get(&self, username: &str) -> Result<Option<User>, StoreError> {
+        let users = self.users.lock().map_err(|_| StoreError::Lock)?;
+        Ok(users.get(username).cloned())
+    }
+
+    pub fn list(&self) -> Result<Vec<User>, StoreError> {
+        let users = self.users.lock().map_err(|_| StoreError::Lock)?;
+        let mut users: Vec<User> = users.values().cloned().collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(users)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, StoreError> {
+        Ok(self.users.lock().map_err(|_| StoreError::Lock)?.is_empty())
+    }
+
+    /// Applies `f` to the user map and persists the result. Nothing is
+    /// written if `f` returns an error.
+    pub fn modify<R>(
+        &self,
+        f: impl FnOnce(&mut HashMap<String, User>) -> Result<R, String>,
+    ) -> Result<Result<R, String>, StoreError> {
+        let mut users = self.users.lock().map_err(|_| StoreError::Lock)?;
+        let mut next = users.clone();
+        let result = match f(&mut next) {
+            Ok(result) => result,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        persist(&self.path, &next)?;
+        *users = next;
+        Ok(Ok(result))
+    }
+}
 
 #[derive(Deserialize)]
 pub struct LoginForm {
@@ -21,60 +205,230 @@ pub struct LoginForm {
     password: String,
 }
 
+#[derive(Deserialize)]
+pub struct TwoFactorForm {
+    code: String,
+}
+
 #[derive(Template)]
 #[template(path = "login.html")]
 struct LoginTemplate {
+    csrf_token: String,
     error: Option<String>,
 }
 
+#[derive(Template)]
+#[template(path = "login_2fa.html")]
+struct TwoFactorTemplate {
+    csrf_token: String,
+    error: Option<String>,
+}
+
+// Session key for a login that passed the password check but still needs
+// its second factor
+const PENDING_LOGIN_KEY: &str = "pending_2fa_login";
+const PENDING_LOGIN_TTL_SECS: i64 = 5 * 60;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PendingLogin {
+    username: String,
+    started_at: DateTime<Utc>,
+}
+
+fn pending_login(session: &Session) -> Option<PendingLogin> {
+    session
+        .get::<PendingLogin>(PENDING_LOGIN_KEY)
+        .ok()
+        .flatten()
+        .filter(|p| (Utc::now() - p.started_at).num_seconds() < PENDING_LOGIN_TTL_SECS)
+}
+
+fn throttle_keys(username: &str, request: &HttpRequest) -> Vec<ThrottleKey> {
+    let mut keys = vec![ThrottleKey::User(username.to_string())];
+    if let Some(addr) = request.peer_addr() {
+        keys.push(ThrottleKey::Ip(addr.ip().to_string()));
+    }
+    keys
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::Found()
+        .insert_header(("Location", location))
+        .finish()
+}
+
 // Login page
 #[get("/auth/login")]
-pub async fn login_page(user: Option<Identity>) -> Result<HttpResponse, Error> {
+pub async fn login_page(user: Option<Identity>, csrf: CsrfToken) -> Result<HttpResponse, Error> {
     if user.is_some() {
         return Ok(HttpResponse::Found()
             .insert_header(("Location", "/views/index"))
             .finish());
     }
 
-    let template = LoginTemplate { error: None };
+    let template = LoginTemplate {
+        csrf_token: csrf.0,
+        error: None,
+    };
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(template.render().map_err(ErrorInternalServerError)?))
 }
 
+const INVALID_CREDENTIALS: &str = "Invalid username or password";
+
+// Verified against when the username is unknown, so a miss costs the same
+// Argon2 work as a wrong password and timing doesn't reveal which it was
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(b"not a real password", &salt)
+            .map(|hash| hash.to_string())
+            .unwrap_or_default()
+    })
+}
+
+fn login_error(
+    session: &Session,
+    status: StatusCode,
+    message: &str,
+) -> Result<HttpResponse, Error> {
+    let template = LoginTemplate {
+        csrf_token: csrf::session_token(session)?,
+        error: Some(message.to_string()),
+    };
+
+    Ok(HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(template.render().map_err(ErrorInternalServerError)?))
+}
+
 #[post("/auth/login")]
 pub async fn login(
     users: Data<UserStore>,
+    throttle: Data<LoginThrottle>,
     form: Form<LoginForm>,
+    session: Session,
     request: HttpRequest,
 ) -> Result<HttpResponse, Error> {
-    let users = users.0.lock().unwrap();
-    
-    if let Some(stored_hash) = users.get(&form.username) {
-        let parsed_hash = PasswordHash::new(stored_hash)
-            .map_err(|_| ErrorUnauthorized("Invalid credentials"))?;
-            
-        if Argon2::default()
-            .verify_password(form.password.as_bytes(), &parsed_hash)
-            .is_ok()
-        {
-            Identity::login(&request.extensions(), form.username.clone())
-                .map_err(|_| ErrorInternalServerError("Could not create identity"))?;
-            return Ok(HttpResponse::Found()
-                .insert_header(("Location", "/views/index"))
-                .finish());
+    let keys = throttle_keys(&form.username, &request);
+
+    if throttle.locked_until(&keys)?.is_some() {
+        return login_error(
+            &session,
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed login attempts. Please try again later.",
+        );
+    }
+
+    let user = users.get(&form.username)?.filter(|user| !user.disabled);
+    let stored_hash = user
+        .as_ref()
+        .map(|user| user.password_hash.as_str())
+        .unwrap_or_else(|| dummy_hash());
+    let verified = PasswordHash::new(stored_hash)
+        .map(|parsed_hash| {
+            Argon2::default()
+                .verify_password(form.password.as_bytes(), &parsed_hash)
+                .is_ok()
+        })
+        .unwrap_or(false);
+
+    if let Some(user) = user.filter(|_| verified) {
+        if user.two_factor.enabled() {
+            session
+                .insert(
+                    PENDING_LOGIN_KEY,
+                    PendingLogin {
+                        username: user.username,
+                        started_at: Utc::now(),
+                    },
+                )
+                .map_err(ErrorInternalServerError)?;
+            return Ok(redirect("/auth/2fa"));
         }
+
+        throttle.clear(&keys[0])?;
+        Identity::login(&request.extensions(), form.username.clone())
+            .map_err(|_| ErrorInternalServerError("Could not create identity"))?;
+        return Ok(redirect("/views/index"));
     }
-    
-    let template = LoginTemplate {
-        error: Some("Invalid username or password".to_string()),
+
+    log::warn!("Failed login attempt for '{}'", form.username);
+    throttle.record_failure(&keys)?;
+    login_error(&session, StatusCode::OK, INVALID_CREDENTIALS)
+}
+
+fn two_factor_error(
+    session: &Session,
+    status: StatusCode,
+    message: &str,
+) -> Result<HttpResponse, Error> {
+    let template = TwoFactorTemplate {
+        csrf_token: csrf::session_token(session)?,
+        error: Some(message.to_string()),
+    };
+
+    Ok(HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(template.render().map_err(ErrorInternalServerError)?))
+}
+
+// Second login step for users with two-factor authentication
+#[get("/auth/2fa")]
+pub async fn two_factor_page(session: Session, csrf: CsrfToken) -> Result<HttpResponse, Error> {
+    if pending_login(&session).is_none() {
+        return Ok(redirect("/auth/login"));
+    }
+
+    let template = TwoFactorTemplate {
+        csrf_token: csrf.0,
+        error: None,
     };
-    
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(template.render().map_err(ErrorInternalServerError)?))
 }
 
+#[post("/auth/2fa")]
+pub async fn two_factor_login(
+    users: Data<UserStore>,
+    throttle: Data<LoginThrottle>,
+    form: Form<TwoFactorForm>,
+    session: Session,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let Some(pending) = pending_login(&session) else {
+        session.remove(PENDING_LOGIN_KEY);
+        return Ok(redirect("/auth/login"));
+    };
+    let keys = throttle_keys(&pending.username, &request);
+
+    if throttle.locked_until(&keys)?.is_some() {
+        return two_factor_error(
+            &session,
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed login attempts. Please try again later.",
+        );
+    }
+
+    let verified = two_factor::verify(&users, &pending.username, &form.code)
+        .map_err(ErrorInternalServerError)?;
+    if !verified {
+        log::warn!("Failed two-factor attempt for '{}'", pending.username);
+        throttle.record_failure(&keys)?;
+        return two_factor_error(&session, StatusCode::OK, "Invalid authentication code");
+    }
+
+    session.remove(PENDING_LOGIN_KEY);
+    throttle.clear(&keys[0])?;
+    Identity::login(&request.extensions(), pending.username)
+        .map_err(|_| ErrorInternalServerError("Could not create identity"))?;
+    Ok(redirect("/views/index"))
+}
+
 #[post("/auth/logout")]
 pub async fn logout(user: Identity) -> Result<HttpResponse, Error> {
     user.logout();
@@ -83,16 +437,65 @@ pub async fn logout(user: Identity) -> Result<HttpResponse, Error> {
         .finish())
 }
 
-pub fn create_user(users: &UserStore, username: &str, password: &str) -> Result<(), String> {
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LEN
+        ));
+    }
+
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
-    
-    let password_hash = argon2
+
+    argon2
         .hash_password(password.as_bytes(), &salt)
-        .map_err(|e| format!("Failed to hash password: {}", e))?
-        .to_string();
-    
-    let mut users = users.0.lock().unwrap();
-    users.insert(username.to_string(), password_hash);
-    Ok(())
-}
\ No newline at end of file
+        .map_err(|e| format!("Failed to hash password: {}", e))
+        .map(|hash| hash.to_string())
+}
+
+pub fn create_user(
+    users: &UserStore,
+    username: &str,
+    password: &str,
+    role: Role,
+    languages: Vec<Language>,
+) -> Result<(), String> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err("Username must not be empty".to_string());
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_alphanumeric() || "._-@".contains(c))
+    {
+        return Err(
+            "Username may only contain letters, digits and the characters . _ - @".to_string(),
+        );
+    }
+    let password_hash = hash_password(password)?;
+
+    users
+        .modify(|users| {
+            if users.contains_key(username) {
+                return Err(format!("User '{}' already exists", username));
+            }
+            users.insert(
+                username.to_string(),
+                User {
+                    username: username.to_string(),
+                    password_hash,
+                    disabled: false,
+                    created_at: Utc::now(),
+                    role,
+                    languages,
+                    two_factor: TwoFactor::default(),
+                },
+            );
+            Ok(())
+        })
+        .map_err(|e| e.to_string())?
+}
+
@@ -1,11 +1,16 @@
 use actix_identity::IdentityExt;
 use actix_web::{
     dev::{ServiceRequest, ServiceResponse, Transform, Service},
-    Error, HttpResponse,
+    web::Data,
+    Error, HttpMessage, HttpResponse,
 };
 use std::future::{ready, Ready, Future};
 use std::pin::Pin;
 use actix_web::body::EitherBody;
+use serde_json::json;
+
+use crate::api_tokens::{self, ApiTokenStore};
+use crate::auth::{AuthSettings, Role, UserStore};
 
 pub struct AuthMiddleware;
 
@@ -45,7 +50,98 @@ where
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        if req.get_identity().is_ok() {
+        // Requests with a bearer token are authenticated by the token alone,
+        // never by the session cookie that may come with them
+        if let Some(token) = api_tokens::bearer_token(&req) {
+            if !api_tokens::accepts_tokens(req.path()) {
+                return Box::pin(async move {
+                    let (http_request, _payload) = req.into_parts();
+                    let response = HttpResponse::Forbidden()
+                        .content_type("application/json")
+                        .json(json!({
+                            "error": "API tokens not accepted",
+                            "details": format!(
+                                "API tokens can only be used with {}",
+                                api_tokens::TOKEN_PATH
+                            )
+                        }))
+                        .map_into_right_body();
+                    Ok(ServiceResponse::new(http_request, response))
+                });
+            }
+
+            let token_user = req
+                .app_data::<Data<ApiTokenStore>>()
+                .and_then(|tokens| match api_tokens::authenticate(tokens, token) {
+                    Ok(found) => found,
+                    Err(e) => {
+                        log::error!("Failed to check API token: {}", e);
+                        None
+                    }
+                })
+                .map(|token| token.as_user());
+
+            return match token_user {
+                Some(user) => {
+                    req.extensions_mut().insert(user);
+                    let fut = self.service.call(req);
+                    Box::pin(async move {
+                        let res = fut.await?;
+                        Ok(res.map_into_left_body())
+                    })
+                }
+                None => Box::pin(async move {
+                    let (http_request, _payload) = req.into_parts();
+                    let response = HttpResponse::Unauthorized()
+                        .insert_header(("WWW-Authenticate", "Bearer"))
+                        .content_type("application/json")
+                        .json(json!({
+                            "error": "Invalid API token",
+                            "details": "The token is unknown or has been revoked"
+                        }))
+                        .map_into_right_body();
+                    Ok(ServiceResponse::new(http_request, response))
+                }),
+            };
+        }
+
+        let active_user = req.get_identity().ok().and_then(|identity| {
+            let username = identity.id().ok()?;
+            let users = req.app_data::<Data<UserStore>>()?;
+            match users.get(&username) {
+                Ok(Some(user)) if !user.disabled => Some(user),
+                _ => {
+                    // Account was disabled or removed after this session began
+                    identity.logout();
+                    None
+                }
+            }
+        });
+
+        // Admins who still need to enroll in 2FA can only reach the account page
+        let enrollment_required = active_user.as_ref().is_some_and(|user| {
+            req.app_data::<Data<AuthSettings>>()
+                .is_some_and(|settings| settings.require_admin_2fa)
+                && user.role >= Role::Admin
+                && !user.two_factor.enabled()
+        });
+        if enrollment_required
+            && req.path() != "/views/account"
+            && !req.path().starts_with("/account/2fa/")
+        {
+            return Box::pin(async move {
+                let (http_request, _payload) = req.into_parts();
+                let response = HttpResponse::Found()
+                    .insert_header(("Location", "/views/account"))
+                    .finish()
+                    .map_into_right_body();
+                Ok(ServiceResponse::new(http_request, response))
+            });
+        }
+
+        if let Some(user) = active_user {
+            // Make the account available to handlers through `auth::CurrentUser`
+            req.extensions_mut().insert(user);
             let fut = self.service.call(req);
             Box::pin(async move {
                 let res = fut.await?;
@@ -62,4 +158,57 @@ where
             })
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{self, Language};
+    use crate::api_tokens::{create_token, TokenScope};
+    use crate::store::LocalStore;
+    use crate::types::EngagementRepo;
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        web, App,
+    };
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[actix_web::test]
+    async fn bearer_tokens_reach_only_the_engagement_api() {
+        let path = std::env::temp_dir().join(format!("tokens-test-{}.json", Uuid::new_v4()));
+        let tokens = ApiTokenStore::open(&path).unwrap();
+        let (_, token) =
+            create_token(&tokens, "site", TokenScope::ReadOnly, vec![Language::English], "admin")
+                .unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(tokens))
+                .app_data(Data::new(EngagementRepo::new(Arc::new(LocalStore::in_memory()))))
+                .wrap(AuthMiddleware)
+                .service(api::get_engs)
+                .route("/users", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let bearer = ("Authorization", format!("Bearer {}", token));
+
+        for (uri, status) in [
+            ("/engs/English", StatusCode::OK),
+            ("/users", StatusCode::FORBIDDEN),
+            ("/engsx", StatusCode::FORBIDDEN),
+        ] {
+            let req = TestRequest::get()
+                .uri(uri)
+                .insert_header(bearer.clone())
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), status, "{}", uri);
+        }
+
+        let req = TestRequest::get()
+            .uri("/engs/English")
+            .insert_header(("Authorization", "Bearer apt_unknown"))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,22 +1,33 @@
-use aws_config::{meta::region::RegionProviderChain, BehaviorVersion, Region};
 use aws_sdk_s3::error::SdkError as AwsSdkError;
-use aws_sdk_s3::{primitives::ByteStream, Client as S3Client};
-use chrono::{Duration, TimeZone, Utc};
-use std::collections::HashSet;
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::{
     error::Error as StdError,
     io::Cursor,
+    path::PathBuf,
     sync::{Arc, Mutex},
 };
 use thiserror::Error;
 use tokio::time::interval;
 
-use crate::api::Engagement;
+use crate::api::{Engagement, TrashedEngagement};
+use crate::audit::{AuditLog, AuditRecord};
+use crate::backup_crypto::{BackupKeyring, ENCRYPTION_ALGORITHM};
+use crate::backup_target::{BackupTarget, LocalTarget, S3Target};
+use crate::hosts::Host;
+use crate::instructors::Instructor;
+use crate::journal::Journal;
+use crate::roster;
+use crate::store::StoreError;
+use crate::types::{EngagementRepo, HostRepo, InstructorRepo, TrashRepo};
 
 #[derive(Debug, Error)]
 pub enum BackupError {
+    /// Boxed because the SDK error is large enough to bloat every `Result`
+    /// returning a `BackupError`
     #[error("AWS SDK error: {0}")]
-    AwsError(#[from] aws_sdk_s3::Error),
+    AwsError(Box<aws_sdk_s3::Error>),
 
     #[error("Compression error: {0}")]
     CompressionError(#[from] std::io::Error),
@@ -27,13 +38,31 @@ pub enum BackupError {
     #[error("Environment variable error: {0}")]
     EnvError(#[from] std::env::VarError),
 
+    #[error("Store error: {0}")]
+    StoreError(#[from] StoreError),
+
     #[error("AWS operation error: {0}")]
     AwsOperationError(String),
 
+    #[error("Backup target error: {0}")]
+    TargetError(String),
+
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+
+    #[error("Integrity error: {0}")]
+    IntegrityError(String),
+
     #[error("Unknown error: {0}")]
     Unknown(#[from] Box<dyn StdError + Send + Sync>),
 }
 
+impl From<aws_sdk_s3::Error> for BackupError {
+    fn from(err: aws_sdk_s3::Error) -> Self {
+        BackupError::AwsError(Box::new(err))
+    }
+}
+
 impl<E> From<AwsSdkError<E>> for BackupError
 where
     E: std::fmt::Debug,
@@ -43,34 +72,241 @@ where
     }
 }
 
+/// Schema version written into new archives. Bump this together with a new
+/// entry in `MIGRATIONS` whenever the shape of `BackupData` changes.
+pub const BACKUP_SCHEMA_VERSION: u32 = 5;
+
+// Backups listed per page when a request doesn't say, and the most it may ask for
+const BACKUP_PAGE_SIZE: usize = 20;
+const MAX_BACKUP_PAGE_SIZE: usize = 100;
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct BackupData {
-    engagements: HashSet<Engagement>,
-    instructors: HashSet<String>,
-    hosts: HashSet<String>,
+pub struct BackupData {
+    pub engagements: HashSet<Engagement>,
+    pub trash: HashSet<TrashedEngagement>,
+    pub instructors: HashSet<Instructor>,
+    pub hosts: HashSet<Host>,
+    pub audit: Vec<AuditRecord>,
+}
+
+/// Top-level archive layout from version 2 onwards. Version 1 archives are a
+/// bare `BackupData` object with no envelope.
+#[derive(serde::Serialize)]
+struct BackupEnvelope<'a> {
+    version: u32,
+    data: &'a BackupData,
+}
+
+struct FetchedBackup {
+    data: BackupData,
+    schema_version: u32,
+    checksum_verified: bool,
+}
+
+type Migration = fn(serde_json::Value) -> Result<serde_json::Value, BackupError>;
+
+/// `MIGRATIONS[n]` upgrades archive data from version `n + 1` to `n + 2`.
+const MIGRATIONS: &[Migration] = &[
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+];
+
+/// Version 2 only introduced the envelope; the data itself is unchanged.
+fn migrate_v1_to_v2(data: serde_json::Value) -> Result<serde_json::Value, BackupError> {
+    Ok(data)
+}
+
+/// Version 3 added the audit log. Older archives predate it, so they start
+/// with an empty history.
+fn migrate_v2_to_v3(mut data: serde_json::Value) -> Result<serde_json::Value, BackupError> {
+    let object = data.as_object_mut().ok_or_else(|| {
+        BackupError::IntegrityError("Backup data is not a JSON object".to_string())
+    })?;
+    object.insert("audit".to_string(), serde_json::Value::Array(Vec::new()));
+    Ok(data)
+}
+
+/// Version 4 added the trash. Engagements deleted before it were removed
+/// outright, so older archives have nothing to put in it.
+fn migrate_v3_to_v4(mut data: serde_json::Value) -> Result<serde_json::Value, BackupError> {
+    let object = data.as_object_mut().ok_or_else(|| {
+        BackupError::IntegrityError("Backup data is not a JSON object".to_string())
+    })?;
+    object.insert("trash".to_string(), serde_json::Value::Array(Vec::new()));
+    Ok(data)
+}
+
+/// Version 5 made instructors and hosts records with ids, referenced by id
+/// from engagements. Names get ids derived from them, so every old archive
+/// upgrades to the same ids.
+fn migrate_v4_to_v5(mut data: serde_json::Value) -> Result<serde_json::Value, BackupError> {
+    if !data.is_object() {
+        return Err(BackupError::IntegrityError(
+            "Backup data is not a JSON object".to_string(),
+        ));
+    }
+    roster::upgrade_legacy_data(&mut data);
+    Ok(data)
+}
+
+/// Parses a decompressed archive of any known version and upgrades it to the
+/// current `BackupData` shape.
+fn decode_backup(json: &[u8]) -> Result<(BackupData, u32), BackupError> {
+    let mut raw: serde_json::Value = serde_json::from_slice(json)?;
+
+    let (archive_version, mut data) = match raw.get("version").and_then(|v| v.as_u64()) {
+        Some(version) => (version as u32, raw["data"].take()),
+        None => (1, raw),
+    };
+
+    if archive_version == 0 || archive_version > BACKUP_SCHEMA_VERSION {
+        return Err(BackupError::IntegrityError(format!(
+            "Unsupported backup schema version {} (this build reads up to {})",
+            archive_version, BACKUP_SCHEMA_VERSION
+        )));
+    }
+
+    for migration in &MIGRATIONS[(archive_version - 1) as usize..] {
+        data = migration(data)?;
+    }
+
+    Ok((serde_json::from_value(data)?, archive_version))
+}
+
+/// A backup archive available for restore, with the counts recorded in its
+/// object metadata by `perform_backup`.
+#[derive(Debug, serde::Serialize)]
+pub struct BackupSummary {
+    pub name: String,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub size: i64,
+    pub message_count: Option<usize>,
+    pub instructor_count: Option<usize>,
+    pub host_count: Option<usize>,
+}
+
+/// Which page of the backup listing to show, e.g. `?offset=20&limit=20`.
+#[derive(serde::Deserialize)]
+pub struct BackupPageQuery {
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl BackupPageQuery {
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(BACKUP_PAGE_SIZE)
+            .clamp(1, MAX_BACKUP_PAGE_SIZE)
+    }
+}
+
+/// One page of the backup listing, newest first.
+#[derive(Debug, serde::Serialize)]
+pub struct BackupPage {
+    pub backups: Vec<BackupSummary>,
+    /// How many backups come before this page
+    pub offset: usize,
+    /// How many backups there are across every page
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub enum RestoreMode {
+    /// Discard current state and use the archive contents as-is
+    Replace,
+    /// Add archived records that are missing, keeping current records as they are
+    Merge,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct InvalidEngagement {
+    pub id: uuid::Uuid,
+    pub title: String,
+    pub date: String,
+    pub error: String,
+}
+
+/// Outcome of decoding a backup without applying it to live state.
+#[derive(Debug, serde::Serialize)]
+pub struct DryRunReport {
+    pub name: String,
+    pub schema_version: u32,
+    pub checksum_verified: bool,
+    pub eng_count: usize,
+    pub trash_count: usize,
+    pub instructor_count: usize,
+    pub host_count: usize,
+    pub audit_count: usize,
+    pub invalid_engagements: Vec<InvalidEngagement>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RestoreSummary {
+    pub name: String,
+    pub eng_count: usize,
+    pub trash_count: usize,
+    pub instructor_count: usize,
+    pub host_count: usize,
+}
+
+#[derive(Clone, Debug)]
+pub enum BackupTargetConfig {
+    S3 {
+        bucket_name: String,
+        prefix: String,
+        region: String,
+    },
+    Local {
+        dir: PathBuf,
+    },
 }
 
 #[derive(Clone, Debug)]
 pub struct BackupConfig {
-    pub bucket_name: String,
-    pub prefix: String,
-    pub region: String,
+    pub target: BackupTargetConfig,
     pub retention_days: i64,
+    /// Number of most recent backups kept regardless of `retention_days`
+    pub min_keep: usize,
     pub backup_interval_hours: u64,
     pub compression_level: i32,
+    /// Re-download and decode each backup after uploading it
+    pub verify_after_upload: bool,
+    pub keyring: BackupKeyring,
+    /// Restore archives without a key id even though a keyring is set. Only
+    /// needed for archives written before encryption was turned on; without
+    /// it, anyone able to write to the target could plant one.
+    pub allow_plaintext: bool,
 }
 
 impl BackupConfig {
     pub fn from_env() -> Result<Self, BackupError> {
+        let target = match std::env::var("BACKUP_TARGET").as_deref() {
+            Ok("local") => BackupTargetConfig::Local {
+                dir: std::env::var("BACKUP_LOCAL_DIR")
+                    .unwrap_or_else(|_| "backups".to_string())
+                    .into(),
+            },
+            _ => BackupTargetConfig::S3 {
+                bucket_name: std::env::var("AWS_BACKUP_BUCKET")?,
+                prefix: std::env::var("AWS_BACKUP_PREFIX")
+                    .unwrap_or_else(|_| "message-backups".to_string()),
+                region: std::env::var("AWS_REGION")?,
+            },
+        };
+
         Ok(Self {
-            bucket_name: std::env::var("AWS_BACKUP_BUCKET")?,
-            prefix: std::env::var("AWS_BACKUP_PREFIX")
-                .unwrap_or_else(|_| "message-backups".to_string()),
-            region: std::env::var("AWS_REGION")?,
+            target,
             retention_days: std::env::var("BACKUP_RETENTION_DAYS")
                 .unwrap_or_else(|_| "30".to_string())
                 .parse()
                 .unwrap_or(30),
+            min_keep: std::env::var("BACKUP_MIN_KEEP")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()
+                .unwrap_or(3),
             backup_interval_hours: std::env::var("BACKUP_INTERVAL_HOURS")
                 .unwrap_or_else(|_| "24".to_string())
                 .parse()
@@ -79,10 +315,37 @@ impl BackupConfig {
                 .unwrap_or_else(|_| "3".to_string())
                 .parse()
                 .unwrap_or(3),
+            verify_after_upload: std::env::var("BACKUP_VERIFY")
+                .map(|v| v != "false")
+                .unwrap_or(true),
+            keyring: BackupKeyring::from_env()?,
+            allow_plaintext: std::env::var("BACKUP_ALLOW_PLAINTEXT")
+                .map(|v| v == "true")
+                .unwrap_or(false),
         })
     }
+
+    /// Decrypts an archive sealed with `key_id`. Archives with no key id are
+    /// returned as they are only if no keyring is set or plaintext is allowed.
+    fn unseal(
+        &self,
+        name: &str,
+        key_id: Option<&String>,
+        archive: Vec<u8>,
+    ) -> Result<Vec<u8>, BackupError> {
+        match key_id {
+            Some(key_id) => self.keyring.decrypt(key_id, &archive),
+            None if self.keyring.active_key_id().is_none() || self.allow_plaintext => Ok(archive),
+            None => Err(BackupError::EncryptionError(format!(
+                "{} is not encrypted; set BACKUP_ALLOW_PLAINTEXT=true to restore archives \
+                 written before encryption was enabled",
+                name
+            ))),
+        }
+    }
 }
 
+#[derive(Clone, Debug, serde::Serialize)]
 pub struct BackupMetrics {
     pub eng_count: usize,
     pub instructor_count: usize,
@@ -90,94 +353,158 @@ pub struct BackupMetrics {
     pub compressed_size: usize,
     pub compression_time_ms: u128,
     pub upload_time_ms: u128,
+    pub verify_time_ms: Option<u128>,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct BackupStatus {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_metrics: Option<BackupMetrics>,
+    pub next_run: Option<DateTime<Utc>>,
 }
 
 pub struct BackupSystem {
-    engagements: Arc<Mutex<HashSet<Engagement>>>,
-    instructors: Arc<Mutex<HashSet<String>>>,
-    hosts: Arc<Mutex<HashSet<String>>>,
+    engagements: EngagementRepo,
+    trash: TrashRepo,
+    instructors: InstructorRepo,
+    hosts: HostRepo,
+    journal: Arc<Journal>,
+    audit: Arc<AuditLog>,
     config: BackupConfig,
-    client: S3Client,
+    target: Box<dyn BackupTarget>,
+    status: Mutex<BackupStatus>,
+    // Serializes backup runs and restores, so journal truncation follows
+    // snapshot order and no snapshot is taken halfway through a restore
+    run_lock: tokio::sync::Mutex<()>,
 }
 
 impl BackupSystem {
     pub async fn new(
-        engagements: Arc<Mutex<HashSet<Engagement>>>,
-        instructors: Arc<Mutex<HashSet<String>>>,
-        hosts: Arc<Mutex<HashSet<String>>>,
+        engagements: EngagementRepo,
+        trash: TrashRepo,
+        instructors: InstructorRepo,
+        hosts: HostRepo,
+        journal: Arc<Journal>,
+        audit: Arc<AuditLog>,
         config: BackupConfig,
     ) -> Result<Self, BackupError> {
-        let region = Region::new(config.region.clone());
-        let region_provider = RegionProviderChain::first_try(region).or_default_provider();
-        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
-            .region(region_provider)
-            .load()
-            .await;
-
-        let client = S3Client::new(&sdk_config);
+        let target: Box<dyn BackupTarget> = match &config.target {
+            BackupTargetConfig::S3 {
+                bucket_name,
+                prefix,
+                region,
+            } => Box::new(S3Target::new(bucket_name.clone(), prefix.clone(), region.clone()).await),
+            BackupTargetConfig::Local { dir } => Box::new(LocalTarget::new(dir.clone())?),
+        };
 
         Ok(Self {
             engagements,
+            trash,
             instructors,
             hosts,
+            journal,
+            audit,
             config,
-            client,
+            target,
+            status: Mutex::new(BackupStatus::default()),
+            run_lock: tokio::sync::Mutex::new(()),
         })
     }
 
-    pub async fn start_backup_task(self) {
+    pub async fn start_backup_task(self: Arc<Self>) {
         let interval_secs = self.config.backup_interval_hours * 3600;
         let mut interval = interval(tokio::time::Duration::from_secs(interval_secs));
 
         log::info!(
-            "Starting backup task with prefix {}, bucket {}, and interval {}",
-            self.config.prefix,
-            self.config.bucket_name,
+            "Starting backup task with {} and interval {}",
+            self.target.describe(),
             self.config.backup_interval_hours
         );
 
         tokio::spawn(async move {
             loop {
                 interval.tick().await;
-                match self.perform_backup().await {
-                    Ok(metrics) => {
-                        log::info!(
-                            "Backup task completed for {} messages, {} instructors, {} hosts, having compressed size {} bytes taking {} ms to compress, and uploaded in {} ms",
-                            metrics.eng_count,
-                            metrics.instructor_count, 
-                            metrics.host_count,
-                            metrics.compressed_size,
-                            metrics.compression_time_ms,
-                            metrics.upload_time_ms,
-                        );
-                    }
-                    Err(e) => {
-                        log::error!("Backup failed: {}", e);
-                    }
+                if let Ok(mut status) = self.status.lock() {
+                    status.next_run =
+                        Some(Utc::now() + Duration::seconds(interval_secs as i64));
+                }
+                if let Err(e) = self.run_backup().await {
+                    log::error!("Backup failed: {}", e);
                 }
             }
         });
     }
 
+    pub fn status(&self) -> BackupStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or_default()
+    }
+
+    /// Performs a backup immediately and records the outcome in `status`.
+    pub async fn run_backup(&self) -> Result<BackupMetrics, BackupError> {
+        let _running = self.run_lock.lock().await;
+        self.run_backup_locked().await
+    }
+
+    /// `run_backup` for callers already holding `run_lock`.
+    async fn run_backup_locked(&self) -> Result<BackupMetrics, BackupError> {
+        let result = self.perform_backup().await;
+
+        let mut status = self
+            .status
+            .lock()
+            .map_err(|_| BackupError::StoreError(StoreError::Lock))?;
+        match &result {
+            Ok(metrics) => {
+                log::info!(
+                    "Backup task completed for {} messages, {} instructors, {} hosts, having compressed size {} bytes taking {} ms to compress, and uploaded in {} ms",
+                    metrics.eng_count,
+                    metrics.instructor_count,
+                    metrics.host_count,
+                    metrics.compressed_size,
+                    metrics.compression_time_ms,
+                    metrics.upload_time_ms,
+                );
+                status.last_success = Some(Utc::now());
+                status.last_metrics = Some(metrics.clone());
+            }
+            Err(e) => {
+                status.last_failure = Some(Utc::now());
+                status.last_error = Some(e.to_string());
+            }
+        }
+
+        result
+    }
+
     async fn perform_backup(&self) -> Result<BackupMetrics, BackupError> {
         let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
-        let key = format!("{}/backup_{}.json.zst", self.config.prefix, timestamp);
-
-        // Serialize engagements in a separate scope so the lock is dropped
-        let (json, eng_count, instructor_count, host_count) = {
-            let engagements = self.engagements.lock().unwrap();
-            let instructors = self.instructors.lock().unwrap();
-            let hosts = self.hosts.lock().unwrap();
-
-            let backup_data = BackupData {
-                engagements: engagements.clone(),
-                instructors: instructors.clone(),
-                hosts: hosts.clone(),
-            };
-
-            let json = serde_json::to_string(&backup_data)?;
-            (json, engagements.len(), instructors.len(), hosts.len())
+        let name = format!("backup_{}.json.zst", timestamp);
+
+        // Entries journaled before this point are covered by the snapshot below
+        let journal_len = self.journal.len()?;
+        let backup_data = BackupData {
+            engagements: self.engagements.list()?.into_iter().collect(),
+            trash: self.trash.list()?.into_iter().collect(),
+            instructors: self.instructors.list()?.into_iter().collect(),
+            hosts: self.hosts.list()?.into_iter().collect(),
+            audit: self.audit.entries()?,
         };
+        let (eng_count, trash_count, instructor_count, host_count, audit_count) = (
+            backup_data.engagements.len(),
+            backup_data.trash.len(),
+            backup_data.instructors.len(),
+            backup_data.hosts.len(),
+            backup_data.audit.len(),
+        );
+        let json = serde_json::to_string(&BackupEnvelope {
+            version: BACKUP_SCHEMA_VERSION,
+            data: &backup_data,
+        })?;
 
         let compression_start = std::time::Instant::now();
         let compressed =
@@ -185,26 +512,50 @@ impl BackupSystem {
         let compression_time = compression_start.elapsed();
         let compressed_size = compressed.len();
 
+        let mut metadata = HashMap::from([
+            ("compressed_size".to_string(), compressed_size.to_string()),
+            ("message_count".to_string(), eng_count.to_string()),
+            ("instructor_count".to_string(), instructor_count.to_string()),
+            ("host_count".to_string(), host_count.to_string()),
+            ("schema_version".to_string(), BACKUP_SCHEMA_VERSION.to_string()),
+        ]);
+
+        let archive = match self.config.keyring.active_key_id() {
+            Some(key_id) => {
+                metadata.insert("encryption".to_string(), ENCRYPTION_ALGORITHM.to_string());
+                metadata.insert("key_id".to_string(), key_id.to_string());
+                self.config.keyring.encrypt(&compressed)?
+            }
+            None => compressed,
+        };
+        metadata.insert("sha256".to_string(), sha256_hex(&archive));
+
         let upload_start = std::time::Instant::now();
-        self.client
-            .put_object()
-            .bucket(&self.config.bucket_name)
-            .key(&key)
-            .body(ByteStream::from(compressed))
-            .content_type("application/zstd+bincode")
-            .storage_class(aws_sdk_s3::types::StorageClass::StandardIa)
-            .metadata("compressed_size", compressed_size.to_string())
-            .metadata("message_count", eng_count.to_string())
-            .metadata("instructor_count", instructor_count.to_string())
-            .metadata("host_count", host_count.to_string())
-            .send()
-            .await
-            .map_err(BackupError::from)?;
+        self.target.put(&name, archive, metadata).await?;
         let upload_time = upload_start.elapsed();
 
-        self.cleanup_old_backups()
-            .await
-            .map_err(|e| BackupError::Unknown(e.to_string().into()))?;
+        let verify_time = if self.config.verify_after_upload {
+            let verify_start = std::time::Instant::now();
+            let verified = self.fetch_backup(&name).await?.data;
+            if verified.engagements.len() != eng_count
+                || verified.trash.len() != trash_count
+                || verified.instructors.len() != instructor_count
+                || verified.hosts.len() != host_count
+                || verified.audit.len() != audit_count
+            {
+                return Err(BackupError::IntegrityError(format!(
+                    "Backup {} decoded with different record counts than were written",
+                    name
+                )));
+            }
+            Some(verify_start.elapsed().as_millis())
+        } else {
+            None
+        };
+
+        self.journal.truncate_through(journal_len)?;
+
+        self.cleanup_old_backups().await?;
 
         Ok(BackupMetrics {
             eng_count,
@@ -213,74 +564,503 @@ impl BackupSystem {
             compressed_size,
             compression_time_ms: compression_time.as_millis(),
             upload_time_ms: upload_time.as_millis(),
+            verify_time_ms: verify_time,
         })
     }
 
-    async fn cleanup_old_backups(&self) -> Result<(), Box<dyn StdError>> {
+    async fn cleanup_old_backups(&self) -> Result<(), BackupError> {
         let cutoff_date = Utc::now() - Duration::days(self.config.retention_days);
 
-        let objects = self
-            .client
-            .list_objects_v2()
-            .bucket(&self.config.bucket_name)
-            .prefix(&self.config.prefix)
-            .send()
-            .await?;
-
-        for object in objects.contents() {
-            if let (Some(key), Some(last_modified)) = (object.key(), object.last_modified()) {
-                if let Ok(millis) = last_modified.to_millis() {
-                    let last_modified = Utc.timestamp_millis_opt(millis).unwrap();
-
-                    if last_modified < cutoff_date {
-                        self.client
-                            .delete_object()
-                            .bucket(&self.config.bucket_name)
-                            .key(key)
-                            .send()
-                            .await?;
-                    }
-                }
-            }
+        let mut objects = self.target.list().await?;
+        objects.sort_by_key(|obj| std::cmp::Reverse(obj.last_modified));
+
+        let expired: Vec<String> = objects
+            .into_iter()
+            .skip(self.config.min_keep)
+            .filter(|obj| {
+                obj.last_modified
+                    .is_some_and(|last_modified| last_modified < cutoff_date)
+            })
+            .map(|obj| obj.name)
+            .collect();
+
+        if !expired.is_empty() {
+            log::info!("Deleting {} expired backups", expired.len());
+            self.target.delete_many(&expired).await?;
         }
 
         Ok(())
     }
 
-    pub async fn restore_latest_backup(
+    /// Returns true for object names produced by `perform_backup`.
+    pub fn is_backup_name(name: &str) -> bool {
+        name.starts_with("backup_") && name.ends_with(".json.zst") && !name.contains('/')
+    }
+
+    /// Lists `limit` backups, newest first, skipping the first `offset`.
+    /// Every archive is listed to find the newest, but reading an archive's
+    /// counts takes a request of its own, so only those on the page are read.
+    pub async fn list_backups(
         &self,
-    ) -> Result<(HashSet<Engagement>, HashSet<String>, HashSet<String>), Box<dyn std::error::Error>> {
-        let objects = self
-            .client
-            .list_objects_v2()
-            .bucket(&self.config.bucket_name)
-            .prefix(&self.config.prefix)
-            .send()
-            .await?;
-
-        let latest_key = objects
-            .contents()
+        offset: usize,
+        limit: usize,
+    ) -> Result<BackupPage, BackupError> {
+        let mut objects = self.target.list().await?;
+        objects.sort_by_key(|obj| std::cmp::Reverse(obj.last_modified));
+        let total = objects.len();
+
+        let page = objects.into_iter().skip(offset).take(limit);
+        let backups = futures::future::try_join_all(page.map(|object| async move {
+            let metadata = self.target.metadata(&object.name).await?;
+            let count = |field: &str| metadata.get(field).and_then(|v| v.parse().ok());
+
+            Ok::<_, BackupError>(BackupSummary {
+                message_count: count("message_count"),
+                instructor_count: count("instructor_count"),
+                host_count: count("host_count"),
+                name: object.name,
+                last_modified: object.last_modified,
+                size: object.size,
+            })
+        }))
+        .await?;
+
+        Ok(BackupPage {
+            backups,
+            offset,
+            total,
+        })
+    }
+
+    /// Downloads, verifies and decodes a backup, migrating older archives to
+    /// the current schema.
+    async fn fetch_backup(&self, name: &str) -> Result<FetchedBackup, BackupError> {
+        let metadata = self.target.metadata(name).await?;
+        let archive = self.target.get(name).await?;
+
+        let checksum_verified = match metadata.get("sha256") {
+            Some(expected) => {
+                let actual = sha256_hex(&archive);
+                if *expected != actual {
+                    return Err(BackupError::IntegrityError(format!(
+                        "Checksum mismatch for {}: expected {}, got {}",
+                        name, expected, actual
+                    )));
+                }
+                true
+            }
+            None => false,
+        };
+
+        // Archives written before encryption was enabled carry no key id
+        let compressed_data = self.config.unseal(name, metadata.get("key_id"), archive)?;
+        let decompressed = zstd::stream::decode_all(Cursor::new(compressed_data))?;
+        let (data, schema_version) = decode_backup(&decompressed)?;
+
+        Ok(FetchedBackup {
+            data,
+            schema_version,
+            checksum_verified,
+        })
+    }
+
+    /// Decodes and validates a backup without touching live state.
+    pub async fn dry_run_restore(&self, name: &str) -> Result<DryRunReport, BackupError> {
+        let FetchedBackup {
+            data: backup_data,
+            schema_version,
+            checksum_verified,
+        } = self.fetch_backup(name).await?;
+
+        let invalid_engagements = backup_data
+            .engagements
             .iter()
-            .max_by_key(|obj| obj.last_modified())
-            .and_then(|obj| obj.key())
+            .filter_map(|eng| {
+                eng.validate().err().map(|error| InvalidEngagement {
+                    id: eng.id,
+                    title: eng.title.clone(),
+                    date: eng.date.clone(),
+                    error,
+                })
+            })
+            .collect();
+
+        Ok(DryRunReport {
+            name: name.to_string(),
+            schema_version,
+            checksum_verified,
+            eng_count: backup_data.engagements.len(),
+            trash_count: backup_data.trash.len(),
+            instructor_count: backup_data.instructors.len(),
+            host_count: backup_data.hosts.len(),
+            audit_count: backup_data.audit.len(),
+            invalid_engagements,
+        })
+    }
+
+    /// Restores the named backup into the live stores, then takes a fresh
+    /// backup so the restored state becomes the latest snapshot.
+    pub async fn restore_backup(
+        &self,
+        name: &str,
+        mode: RestoreMode,
+    ) -> Result<RestoreSummary, BackupError> {
+        // Held until the backup taken afterwards is done, so no other run
+        // snapshots a half-restored state or truncates the journal meanwhile
+        let _running = self.run_lock.lock().await;
+        let mut backup_data = self.fetch_backup(name).await?.data;
+
+        if let RestoreMode::Merge = mode {
+            backup_data.trash = merge(self.trash.list()?, backup_data.trash);
+            backup_data.engagements = merge(self.engagements.list()?, backup_data.engagements);
+            // An engagement deleted since the backup stays in the trash
+            // rather than coming back from the archive as well
+            let trashed: HashSet<uuid::Uuid> =
+                backup_data.trash.iter().map(|t| t.engagement.id).collect();
+            backup_data.engagements.retain(|e| !trashed.contains(&e.id));
+            backup_data.instructors = merge(self.instructors.list()?, backup_data.instructors);
+            backup_data.hosts = merge(self.hosts.list()?, backup_data.hosts);
+        }
+
+        let summary = RestoreSummary {
+            name: name.to_string(),
+            eng_count: backup_data.engagements.len(),
+            trash_count: backup_data.trash.len(),
+            instructor_count: backup_data.instructors.len(),
+            host_count: backup_data.hosts.len(),
+        };
+
+        self.engagements.replace_all(backup_data.engagements)?;
+        self.trash.replace_all(backup_data.trash)?;
+        self.instructors.replace_all(backup_data.instructors)?;
+        self.hosts.replace_all(backup_data.hosts)?;
+        // In either mode the audit history is only added to, never replaced
+        self.audit.merge(backup_data.audit)?;
+        log::info!("Restored backup {} using {:?} mode", name, mode);
+
+        if let Err(e) = self.run_backup_locked().await {
+            log::error!("Backup after restoring {} failed: {}", name, e);
+        }
+
+        Ok(summary)
+    }
+
+    pub async fn restore_latest_backup(&self) -> Result<BackupData, Box<dyn std::error::Error>> {
+        let objects = self.target.list().await?;
+
+        let latest_name = objects
+            .iter()
+            .max_by_key(|obj| obj.last_modified)
+            .map(|obj| obj.name.as_str())
             .ok_or("No backups found")?;
 
-        let response = self
-            .client
-            .get_object()
-            .bucket(&self.config.bucket_name)
-            .key(latest_key)
-            .send()
-            .await?;
+        Ok(self.fetch_backup(latest_name).await?.data)
+    }
+}
 
-        let compressed_data = response.body.collect().await?.into_bytes();
-        let decompressed = zstd::stream::decode_all(Cursor::new(compressed_data))?;
-        let backup_data: BackupData = serde_json::from_slice(&decompressed)?;
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Unions archived records into the current ones; current records win on
+/// conflicting keys.
+fn merge<T: Eq + std::hash::Hash>(current: Vec<T>, archived: HashSet<T>) -> HashSet<T> {
+    let mut merged: HashSet<T> = current.into_iter().collect();
+    merged.extend(archived);
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Language, Status};
+    use crate::backup_target::BackupObject;
+    use crate::roster::legacy_id;
+    use futures::future::BoxFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use uuid::Uuid;
+
+    /// Decompressed archives as each schema version wrote them
+    const FIXTURES: [(u32, &str); 5] = [
+        (1, include_str!("../tests/fixtures/backup-v1.json")),
+        (2, include_str!("../tests/fixtures/backup-v2.json")),
+        (3, include_str!("../tests/fixtures/backup-v3.json")),
+        (4, include_str!("../tests/fixtures/backup-v4.json")),
+        (5, include_str!("../tests/fixtures/backup-v5.json")),
+    ];
+
+    fn decode_fixture(version: u32) -> BackupData {
+        let (_, json) = FIXTURES[(version - 1) as usize];
+        let (data, archive_version) = decode_backup(json.as_bytes()).unwrap();
+        assert_eq!(archive_version, version);
+        data
+    }
+
+    fn engagement(data: &BackupData, title: &str, part: usize) -> Engagement {
+        data.engagements
+            .iter()
+            .chain(data.trash.iter().map(|t| &t.engagement))
+            .find(|e| e.title == title && e.part == part)
+            .cloned()
+            .unwrap()
+    }
+
+    fn instructor<'a>(data: &'a BackupData, name: &str) -> &'a Instructor {
+        data.instructors.iter().find(|i| i.name == name).unwrap()
+    }
+
+    fn config(keys: &str, allow_plaintext: bool) -> BackupConfig {
+        BackupConfig {
+            target: BackupTargetConfig::Local {
+                dir: std::env::temp_dir(),
+            },
+            retention_days: 30,
+            min_keep: 3,
+            backup_interval_hours: 24,
+            compression_level: 3,
+            verify_after_upload: true,
+            keyring: BackupKeyring::parse(keys).unwrap(),
+            allow_plaintext,
+        }
+    }
+
+    #[test]
+    fn plaintext_archives_need_opting_in_once_encrypted() {
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+        let key = format!("k1:{}", BASE64.encode([7u8; 32]));
+        let archive = b"archive".to_vec();
+
+        assert_eq!(config("", false).unseal("a", None, archive.clone()).unwrap(), archive);
+        assert!(config(&key, false).unseal("a", None, archive.clone()).is_err());
+        assert_eq!(config(&key, true).unseal("a", None, archive.clone()).unwrap(), archive);
+
+        let encrypted = config(&key, false);
+        let sealed = encrypted.keyring.encrypt(&archive).unwrap();
+        let key_id = "k1".to_string();
+        assert_eq!(encrypted.unseal("a", Some(&key_id), sealed).unwrap(), archive);
+    }
+
+    /// A backup system over an empty in-memory store, writing archives to
+    /// `dir/backups`.
+    async fn local_system(dir: &std::path::Path) -> BackupSystem {
+        use crate::store::LocalStore;
+
+        std::fs::create_dir_all(dir).unwrap();
+        let store = Arc::new(LocalStore::in_memory());
+        let config = BackupConfig {
+            target: BackupTargetConfig::Local {
+                dir: dir.join("backups"),
+            },
+            ..config("", false)
+        };
+        BackupSystem::new(
+            EngagementRepo::new(store.clone()),
+            TrashRepo::new(store.clone()),
+            InstructorRepo::new(store.clone()),
+            HostRepo::new(store.clone()),
+            Arc::new(Journal::open(dir.join("journal.jsonl")).unwrap()),
+            Arc::new(AuditLog::open(dir.join("audit.json")).unwrap()),
+            config,
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Counts the metadata requests made of a target.
+    struct CountingTarget {
+        inner: LocalTarget,
+        metadata_reads: Arc<AtomicUsize>,
+    }
+
+    impl BackupTarget for CountingTarget {
+        fn describe(&self) -> String {
+            self.inner.describe()
+        }
+
+        fn put<'a>(
+            &'a self,
+            name: &'a str,
+            data: Vec<u8>,
+            metadata: HashMap<String, String>,
+        ) -> BoxFuture<'a, Result<(), BackupError>> {
+            self.inner.put(name, data, metadata)
+        }
+
+        fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<u8>, BackupError>> {
+            self.inner.get(name)
+        }
+
+        fn metadata<'a>(
+            &'a self,
+            name: &'a str,
+        ) -> BoxFuture<'a, Result<HashMap<String, String>, BackupError>> {
+            self.metadata_reads.fetch_add(1, AtomicOrdering::SeqCst);
+            self.inner.metadata(name)
+        }
+
+        fn list(&self) -> BoxFuture<'_, Result<Vec<BackupObject>, BackupError>> {
+            self.inner.list()
+        }
+
+        fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<(), BackupError>> {
+            self.inner.delete(name)
+        }
+    }
+
+    #[actix_web::test]
+    async fn listing_reads_metadata_for_one_page_only() {
+        let dir = std::env::temp_dir().join(format!("backup-test-{}", Uuid::new_v4()));
+        let mut system = local_system(&dir).await;
+        let archives = dir.join("archives");
+        let metadata_reads = Arc::new(AtomicUsize::new(0));
+        let target = CountingTarget {
+            inner: LocalTarget::new(&archives).unwrap(),
+            metadata_reads: metadata_reads.clone(),
+        };
+        let now = std::time::SystemTime::now();
+        for age in 0..5u64 {
+            let name = format!("backup_{}.json.zst", age);
+            let metadata = HashMap::from([("message_count".to_string(), age.to_string())]);
+            target.put(&name, Vec::new(), metadata).await.unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(archives.join(&name))
+                .unwrap()
+                .set_modified(now - std::time::Duration::from_secs(age * 3600))
+                .unwrap();
+        }
+        system.target = Box::new(target);
+
+        let page = system.list_backups(1, 2).await.unwrap();
+        assert_eq!(page.total, 5);
+        assert_eq!(page.offset, 1);
+        let counts: Vec<_> = page.backups.iter().map(|b| b.message_count).collect();
+        assert_eq!(counts, vec![Some(1), Some(2)]);
+        assert_eq!(metadata_reads.load(AtomicOrdering::SeqCst), 2);
+        assert!(system.list_backups(5, 2).await.unwrap().backups.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn restore_takes_the_run_lock_and_backs_up_after() {
+        use crate::api::NewEngagement;
+
+        let dir = std::env::temp_dir().join(format!("backup-test-{}", Uuid::new_v4()));
+        let system = local_system(&dir).await;
+
+        let engagement = NewEngagement {
+            instructor_id: Uuid::new_v4(),
+            host_id: Uuid::new_v4(),
+            date: "2024-05-01".to_string(),
+            language: Language::English,
+            title: "Intro".to_string(),
+            part: 1,
+            num_parts: 1,
+            status: Status::Planning,
+        }
+        .into_engagement();
+        system.engagements.insert(engagement.clone()).unwrap();
+        system.run_backup().await.unwrap();
+        let name = system.list_backups(0, 1).await.unwrap().backups[0]
+            .name
+            .clone();
+
+        system.engagements.replace_all(HashSet::new()).unwrap();
+        let held = system.run_lock.lock().await;
+        let restore = system.restore_backup(&name, RestoreMode::Replace);
+        let timeout = std::time::Duration::from_millis(200);
+        // Waits for whichever run holds the lock
+        assert!(tokio::time::timeout(timeout, restore).await.is_err());
+        drop(held);
+
+        let summary = system.restore_backup(&name, RestoreMode::Replace).await.unwrap();
+        assert_eq!(summary.eng_count, 1);
+        assert_eq!(system.engagements.list().unwrap(), vec![engagement]);
+        assert!(system.status().last_success.is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn every_version_has_a_fixture() {
+        assert_eq!(FIXTURES.len() as u32, BACKUP_SCHEMA_VERSION);
+        assert_eq!(MIGRATIONS.len() as u32, BACKUP_SCHEMA_VERSION - 1);
+    }
+
+    #[test]
+    fn v1_and_v2_archives_start_with_empty_audit_and_trash() {
+        for version in [1, 2] {
+            let data = decode_fixture(version);
+            assert_eq!(data.engagements.len(), 2);
+            assert!(data.trash.is_empty());
+            assert!(data.audit.is_empty());
+        }
+    }
+
+    #[test]
+    fn v3_archives_keep_their_audit_log() {
+        let data = decode_fixture(3);
+        assert_eq!(data.audit.len(), 1);
+        assert_eq!(data.audit[0].user, "admin");
+        assert!(data.trash.is_empty());
+    }
+
+    #[test]
+    fn v4_archives_keep_their_trash() {
+        let data = decode_fixture(4);
+        assert_eq!(data.engagements.len(), 1);
+        assert_eq!(data.trash.len(), 1);
+        let trashed = data.trash.iter().next().unwrap();
+        assert_eq!(trashed.deleted_by, "admin");
+        assert_eq!(trashed.engagement.language, Language::Spanish);
+    }
+
+    #[test]
+    fn legacy_names_become_roster_records() {
+        for version in 1..=4 {
+            let data = decode_fixture(version);
+            let intro = engagement(&data, "Intro", 1);
+            let ann = instructor(&data, "Ann Lee");
+            assert_eq!(intro.instructor_id, ann.id);
+            assert_eq!(ann.id, legacy_id("instructor", "Ann Lee"));
+            assert!(ann.active);
+            assert_eq!(intro.host_id, legacy_id("host", "Bob Ray"));
+            assert_eq!(intro.version, 0);
+            assert_eq!(data.hosts.len(), 1);
+        }
+    }
+
+    #[test]
+    fn names_missing_from_the_roster_get_inactive_records() {
+        // Cleo was taken off the roster in the v4 archive while still
+        // scheduled on an engagement in the trash
+        let data = decode_fixture(4);
+        let cleo = instructor(&data, "Cleo Diaz");
+        assert!(!cleo.active);
+        assert_eq!(engagement(&data, "Intro", 2).instructor_id, cleo.id);
+
+        assert!(instructor(&decode_fixture(3), "Cleo Diaz").active);
+    }
+
+    #[test]
+    fn v5_archives_decode_unchanged() {
+        let data = decode_fixture(5);
+        let intro = engagement(&data, "Intro", 1);
+        let ann = instructor(&data, "Ann Lee");
+        assert_eq!(intro.instructor_id, ann.id);
+        assert_eq!(ann.languages, vec![Language::English]);
+        assert_eq!(ann.time_zone, "Europe/Madrid");
+        assert_eq!(intro.status, Status::Invited);
+        assert_eq!(intro.version, 3);
+    }
 
-        Ok((
-            backup_data.engagements,
-            backup_data.instructors,
-            backup_data.hosts,
-        ))
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let newer = format!(r#"{{"version": {}, "data": {{}}}}"#, BACKUP_SCHEMA_VERSION + 1);
+        assert!(matches!(
+            decode_backup(newer.as_bytes()),
+            Err(BackupError::IntegrityError(_))
+        ));
+        assert!(decode_backup(br#"{"version": 0, "data": {}}"#).is_err());
     }
 }
@@ -0,0 +1,176 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::collections::HashMap;
+
+use crate::backup::BackupError;
+
+pub const ENCRYPTION_ALGORITHM: &str = "aes-256-gcm";
+const NONCE_LEN: usize = 12;
+
+/// Keys used to encrypt backup archives. New archives are sealed with the
+/// active key; every key in the ring stays available for decrypting older
+/// archives, which is how rotation works.
+///
+/// Keys are given as `id:base64key` entries separated by commas or newlines,
+/// either in `BACKUP_ENCRYPTION_KEYS` or in the file named by
+/// `BACKUP_ENCRYPTION_KEY_FILE`. The first entry is the active key.
+#[derive(Clone, Default)]
+pub struct BackupKeyring {
+    active: Option<String>,
+    keys: HashMap<String, Key<Aes256Gcm>>,
+}
+
+impl std::fmt::Debug for BackupKeyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackupKeyring")
+            .field("active", &self.active)
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl BackupKeyring {
+    pub fn from_env() -> Result<Self, BackupError> {
+        if let Ok(spec) = std::env::var("BACKUP_ENCRYPTION_KEYS") {
+            return Self::parse(&spec);
+        }
+        if let Ok(path) = std::env::var("BACKUP_ENCRYPTION_KEY_FILE") {
+            let spec = std::fs::read_to_string(&path).map_err(|e| {
+                BackupError::EncryptionError(format!("Failed to read key file {}: {}", path, e))
+            })?;
+            return Self::parse(&spec);
+        }
+
+        log::warn!("No backup encryption keys configured; backups will be stored unencrypted");
+        Ok(Self::default())
+    }
+
+    /// Parses keys in the format described above.
+    pub fn parse(spec: &str) -> Result<Self, BackupError> {
+        let mut keyring = Self::default();
+
+        for entry in spec.split([',', '\n']).map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, encoded) = entry.split_once(':').ok_or_else(|| {
+                BackupError::EncryptionError("Key entries must look like id:base64key".to_string())
+            })?;
+            let bytes = BASE64.decode(encoded.trim()).map_err(|e| {
+                BackupError::EncryptionError(format!("Key '{}' is not valid base64: {}", id, e))
+            })?;
+            let key: [u8; 32] = bytes.as_slice().try_into().map_err(|_| {
+                BackupError::EncryptionError(format!(
+                    "Key '{}' must be 32 bytes, got {}",
+                    id,
+                    bytes.len()
+                ))
+            })?;
+
+            let id = id.trim().to_string();
+            // Only one of the keys could decrypt that id's archives
+            if keyring.keys.contains_key(&id) {
+                return Err(BackupError::EncryptionError(format!(
+                    "Key '{}' is listed more than once",
+                    id
+                )));
+            }
+            keyring.active.get_or_insert_with(|| id.clone());
+            keyring.keys.insert(id, Key::<Aes256Gcm>::from(key));
+        }
+
+        Ok(keyring)
+    }
+
+    pub fn active_key_id(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    /// Encrypts with the active key, returning `nonce || ciphertext`. The key
+    /// id is bound as associated data so an archive cannot be relabelled.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, BackupError> {
+        let key_id = self
+            .active
+            .as_deref()
+            .ok_or_else(|| BackupError::EncryptionError("No active encryption key".to_string()))?;
+        let cipher = Aes256Gcm::new(&self.keys[key_id]);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| BackupError::EncryptionError("Encryption failed".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    pub fn decrypt(&self, key_id: &str, sealed: &[u8]) -> Result<Vec<u8>, BackupError> {
+        let key = self.keys.get(key_id).ok_or_else(|| {
+            BackupError::EncryptionError(format!("Unknown encryption key '{}'", key_id))
+        })?;
+        if sealed.len() < NONCE_LEN {
+            return Err(BackupError::EncryptionError(
+                "Encrypted archive is truncated".to_string(),
+            ));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at nonce length");
+        Aes256Gcm::new(key)
+            .decrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                BackupError::EncryptionError(format!(
+                    "Failed to decrypt archive with key '{}'",
+                    key_id
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> String {
+        BASE64.encode([byte; 32])
+    }
+
+    #[test]
+    fn first_key_is_active_and_all_decrypt() {
+        let old = BackupKeyring::parse(&format!("old:{}", key(1))).unwrap();
+        let sealed = old.encrypt(b"archive").unwrap();
+
+        let rotated = BackupKeyring::parse(&format!("new:{},\nold:{}", key(2), key(1))).unwrap();
+        assert_eq!(rotated.active_key_id(), Some("new"));
+        assert_eq!(rotated.decrypt("old", &sealed).unwrap(), b"archive");
+    }
+
+    #[test]
+    fn archives_cannot_be_relabelled() {
+        let keyring = BackupKeyring::parse(&format!("a:{},b:{}", key(1), key(1))).unwrap();
+        let sealed = keyring.encrypt(b"archive").unwrap();
+        assert!(keyring.decrypt("b", &sealed).is_err());
+        assert!(keyring.decrypt("c", &sealed).is_err());
+    }
+
+    #[test]
+    fn bad_key_lists_are_rejected() {
+        assert!(BackupKeyring::parse(&format!("a:{},a:{}", key(1), key(2))).is_err());
+        assert!(BackupKeyring::parse("a:not-base64").is_err());
+        assert!(BackupKeyring::parse(&format!("a:{}", BASE64.encode([1u8; 16]))).is_err());
+        assert!(BackupKeyring::parse(&key(1)).is_err());
+    }
+}
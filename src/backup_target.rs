@@ -0,0 +1,344 @@
+use aws_config::{meta::region::RegionProviderChain, BehaviorVersion, Region};
+use aws_sdk_s3::{
+    primitives::ByteStream,
+    types::{Delete, ObjectIdentifier},
+    Client as S3Client,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::backup::{BackupError, BackupSystem};
+
+/// A stored backup archive as reported by a target listing.
+#[derive(Clone, Debug)]
+pub struct BackupObject {
+    pub name: String,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub size: i64,
+}
+
+/// Somewhere backup archives can be written to and read back from. Archives
+/// are addressed by file name (`backup_<ts>.json.zst`); each target decides
+/// where that name lives.
+pub trait BackupTarget: Send + Sync {
+    fn describe(&self) -> String;
+
+    fn put<'a>(
+        &'a self,
+        name: &'a str,
+        data: Vec<u8>,
+        metadata: HashMap<String, String>,
+    ) -> BoxFuture<'a, Result<(), BackupError>>;
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<u8>, BackupError>>;
+
+    fn metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<HashMap<String, String>, BackupError>>;
+
+    /// Lists every backup archive held by the target, in no particular order.
+    fn list(&self) -> BoxFuture<'_, Result<Vec<BackupObject>, BackupError>>;
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<(), BackupError>>;
+
+    fn delete_many<'a>(&'a self, names: &'a [String]) -> BoxFuture<'a, Result<(), BackupError>> {
+        Box::pin(async move {
+            for name in names {
+                self.delete(name).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+// Upper bound on keys accepted by a single DeleteObjects request
+const S3_DELETE_BATCH_SIZE: usize = 1000;
+
+pub struct S3Target {
+    client: S3Client,
+    bucket_name: String,
+    prefix: String,
+}
+
+impl S3Target {
+    pub async fn new(bucket_name: String, prefix: String, region: String) -> Self {
+        let region = Region::new(region);
+        let region_provider = RegionProviderChain::first_try(region).or_default_provider();
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(region_provider)
+            .load()
+            .await;
+
+        Self {
+            client: S3Client::new(&sdk_config),
+            bucket_name,
+            prefix,
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}/{}", self.prefix, name)
+    }
+}
+
+impl BackupTarget for S3Target {
+    fn describe(&self) -> String {
+        format!("prefix {}, bucket {}", self.prefix, self.bucket_name)
+    }
+
+    fn put<'a>(
+        &'a self,
+        name: &'a str,
+        data: Vec<u8>,
+        metadata: HashMap<String, String>,
+    ) -> BoxFuture<'a, Result<(), BackupError>> {
+        Box::pin(async move {
+            self.client
+                .put_object()
+                .bucket(&self.bucket_name)
+                .key(self.key(name))
+                .body(ByteStream::from(data))
+                .content_type("application/zstd+bincode")
+                .storage_class(aws_sdk_s3::types::StorageClass::StandardIa)
+                .set_metadata(Some(metadata))
+                .send()
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<u8>, BackupError>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .get_object()
+                .bucket(&self.bucket_name)
+                .key(self.key(name))
+                .send()
+                .await?;
+
+            let data = response
+                .body
+                .collect()
+                .await
+                .map_err(|e| BackupError::AwsOperationError(e.to_string()))?
+                .into_bytes();
+            Ok(data.to_vec())
+        })
+    }
+
+    fn metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<HashMap<String, String>, BackupError>> {
+        Box::pin(async move {
+            let head = self
+                .client
+                .head_object()
+                .bucket(&self.bucket_name)
+                .key(self.key(name))
+                .send()
+                .await?;
+            Ok(head.metadata().cloned().unwrap_or_default())
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<BackupObject>, BackupError>> {
+        Box::pin(async move {
+            let mut backups = Vec::new();
+            let mut continuation_token = None;
+
+            loop {
+                let page = self
+                    .client
+                    .list_objects_v2()
+                    .bucket(&self.bucket_name)
+                    .prefix(&self.prefix)
+                    .set_continuation_token(continuation_token)
+                    .send()
+                    .await?;
+
+                backups.extend(page.contents().iter().filter_map(|object| {
+                    let name = object.key()?.rsplit('/').next()?;
+                    if !BackupSystem::is_backup_name(name) {
+                        return None;
+                    }
+                    Some(BackupObject {
+                        name: name.to_string(),
+                        last_modified: object
+                            .last_modified()
+                            .and_then(|t| t.to_millis().ok())
+                            .and_then(|millis| Utc.timestamp_millis_opt(millis).single()),
+                        size: object.size().unwrap_or_default(),
+                    })
+                }));
+
+                match page.next_continuation_token() {
+                    Some(token) if page.is_truncated().unwrap_or(false) => {
+                        continuation_token = Some(token.to_string());
+                    }
+                    _ => break,
+                }
+            }
+
+            Ok(backups)
+        })
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<(), BackupError>> {
+        Box::pin(async move {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket_name)
+                .key(self.key(name))
+                .send()
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn delete_many<'a>(&'a self, names: &'a [String]) -> BoxFuture<'a, Result<(), BackupError>> {
+        Box::pin(async move {
+            for batch in names.chunks(S3_DELETE_BATCH_SIZE) {
+                let objects = batch
+                    .iter()
+                    .map(|name| ObjectIdentifier::builder().key(self.key(name)).build())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| BackupError::AwsOperationError(e.to_string()))?;
+                let delete = Delete::builder()
+                    .set_objects(Some(objects))
+                    .quiet(true)
+                    .build()
+                    .map_err(|e| BackupError::AwsOperationError(e.to_string()))?;
+
+                let response = self
+                    .client
+                    .delete_objects()
+                    .bucket(&self.bucket_name)
+                    .delete(delete)
+                    .send()
+                    .await?;
+
+                if let Some(failed) = response.errors().first() {
+                    return Err(BackupError::AwsOperationError(format!(
+                        "Failed to delete {} of {} backups, first error on {}: {}",
+                        response.errors().len(),
+                        batch.len(),
+                        failed.key().unwrap_or_default(),
+                        failed.message().unwrap_or_default()
+                    )));
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Stores archives in a local directory, with each archive's metadata kept
+/// in a `<name>.meta.json` file alongside it.
+pub struct LocalTarget {
+    dir: PathBuf,
+}
+
+impl LocalTarget {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, BackupError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| io_error(&dir, e))?;
+        Ok(Self { dir })
+    }
+
+    fn meta_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.meta.json", name))
+    }
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> BackupError {
+    BackupError::TargetError(format!("{}: {}", path.display(), err))
+}
+
+impl BackupTarget for LocalTarget {
+    fn describe(&self) -> String {
+        format!("local directory {}", self.dir.display())
+    }
+
+    fn put<'a>(
+        &'a self,
+        name: &'a str,
+        data: Vec<u8>,
+        metadata: HashMap<String, String>,
+    ) -> BoxFuture<'a, Result<(), BackupError>> {
+        Box::pin(async move {
+            let meta_path = self.meta_path(name);
+            fs::write(&meta_path, serde_json::to_vec(&metadata)?)
+                .map_err(|e| io_error(&meta_path, e))?;
+
+            // Write under a temporary name so listings never see a partial archive
+            let path = self.dir.join(name);
+            let tmp_path = self.dir.join(format!("{}.tmp", name));
+            fs::write(&tmp_path, data).map_err(|e| io_error(&tmp_path, e))?;
+            fs::rename(&tmp_path, &path).map_err(|e| io_error(&path, e))?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<u8>, BackupError>> {
+        Box::pin(async move {
+            let path = self.dir.join(name);
+            fs::read(&path).map_err(|e| io_error(&path, e))
+        })
+    }
+
+    fn metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<HashMap<String, String>, BackupError>> {
+        Box::pin(async move {
+            let meta_path = self.meta_path(name);
+            if !meta_path.exists() {
+                return Ok(HashMap::new());
+            }
+            let raw = fs::read(&meta_path).map_err(|e| io_error(&meta_path, e))?;
+            Ok(serde_json::from_slice(&raw)?)
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<BackupObject>, BackupError>> {
+        Box::pin(async move {
+            let mut objects = Vec::new();
+            for entry in fs::read_dir(&self.dir).map_err(|e| io_error(&self.dir, e))? {
+                let entry = entry.map_err(|e| io_error(&self.dir, e))?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !BackupSystem::is_backup_name(&name) {
+                    continue;
+                }
+
+                let file_meta = entry.metadata().map_err(|e| io_error(&entry.path(), e))?;
+                objects.push(BackupObject {
+                    name,
+                    last_modified: file_meta.modified().ok().map(DateTime::<Utc>::from),
+                    size: file_meta.len() as i64,
+                });
+            }
+            Ok(objects)
+        })
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<(), BackupError>> {
+        Box::pin(async move {
+            let path = self.dir.join(name);
+            fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+
+            let meta_path = self.meta_path(name);
+            if meta_path.exists() {
+                fs::remove_file(&meta_path).map_err(|e| io_error(&meta_path, e))?;
+            }
+            Ok(())
+        })
+    }
+}
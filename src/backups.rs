@@ -0,0 +1,156 @@
+use actix_web::{
+    get, post,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde_json::json;
+
+use crate::audit::{AuditAction, AuditLog, AuditRecord};
+use crate::auth::{CurrentUser, Role};
+use crate::backup::{BackupPageQuery, BackupSystem, RestoreMode};
+
+#[derive(serde::Deserialize)]
+pub struct RestoreRequest {
+    pub mode: RestoreMode,
+}
+
+fn backups_unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Backups unavailable",
+            "details": "The backup system is not configured"
+        }))
+}
+
+fn invalid_backup_name(name: &str) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Invalid backup name",
+            "details": format!("'{}' is not a backup archive", name)
+        }))
+}
+
+#[get("/backups")]
+pub async fn get_backups(
+    backups: Option<Data<BackupSystem>>,
+    query: Query<BackupPageQuery>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let Some(backups) = backups else {
+        return Ok(backups_unavailable());
+    };
+
+    let page = backups
+        .list_backups(query.offset, query.limit())
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(page))
+}
+
+#[get("/backups/status")]
+pub async fn get_backup_status(
+    backups: Option<Data<BackupSystem>>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let Some(backups) = backups else {
+        return Ok(backups_unavailable());
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(backups.status()))
+}
+
+#[post("/backups/run")]
+pub async fn run_backup(
+    backups: Option<Data<BackupSystem>>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let Some(backups) = backups else {
+        return Ok(backups_unavailable());
+    };
+
+    match backups.run_backup().await {
+        Ok(metrics) => Ok(HttpResponse::Created()
+            .content_type("application/json; charset=utf-8")
+            .json(metrics)),
+        Err(e) => Ok(HttpResponse::InternalServerError()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Backup failed",
+                "details": e.to_string()
+            }))),
+    }
+}
+
+#[get("/backups/{name}/dry-run")]
+pub async fn dry_run_restore(
+    backups: Option<Data<BackupSystem>>,
+    name: Path<String>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let Some(backups) = backups else {
+        return Ok(backups_unavailable());
+    };
+
+    if !BackupSystem::is_backup_name(&name) {
+        return Ok(invalid_backup_name(&name));
+    }
+
+    match backups.dry_run_restore(&name).await {
+        Ok(report) => Ok(HttpResponse::Ok()
+            .content_type("application/json; charset=utf-8")
+            .json(report)),
+        Err(e) => Ok(HttpResponse::UnprocessableEntity()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Backup is not restorable",
+                "details": e.to_string()
+            }))),
+    }
+}
+
+#[post("/backups/{name}/restore")]
+pub async fn restore_backup(
+    backups: Option<Data<BackupSystem>>,
+    audit: Data<AuditLog>,
+    name: Path<String>,
+    body: Json<RestoreRequest>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let Some(backups) = backups else {
+        return Ok(backups_unavailable());
+    };
+
+    if !BackupSystem::is_backup_name(&name) {
+        return Ok(invalid_backup_name(&name));
+    }
+
+    let summary = backups
+        .restore_backup(&name, body.mode)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    audit.record(
+        AuditRecord::new(&user.username, AuditAction::RestoreBackup, name.as_str())
+            .after(&body.mode),
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(summary))
+}
@@ -0,0 +1,110 @@
+use actix_web::{
+    get,
+    http::StatusCode,
+    web::{Data, Query},
+    HttpResponse,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::api::{compare_engagement_dates, Engagement, Language};
+use crate::api_tokens::{self, ApiTokenStore, TokenScope};
+use crate::ical;
+use crate::roster::{Roster, RosterNames};
+use crate::types::EngagementRepo;
+
+#[derive(serde::Deserialize)]
+pub struct CalendarQuery {
+    /// Calendar apps can't send an Authorization header, so subscription
+    /// URLs carry the API token themselves
+    pub token: String,
+    pub language: Option<Language>,
+    /// Instructor and host are given by id, or by name as in subscription
+    /// URLs from before they had ids
+    pub instructor: Option<String>,
+    pub host: Option<String>,
+}
+
+impl CalendarQuery {
+    fn matches(&self, engagement: &Engagement, names: &RosterNames) -> bool {
+        let refers_to = |filter: &Option<String>, id: &Uuid, name: &str| {
+            filter
+                .as_ref()
+                .is_none_or(|f| *f == id.to_string() || f == name)
+        };
+
+        self.language
+            .as_ref()
+            .is_none_or(|l| engagement.language == *l)
+            && refers_to(
+                &self.instructor,
+                &engagement.instructor_id,
+                names.instructor(&engagement.instructor_id),
+            )
+            && refers_to(
+                &self.host,
+                &engagement.host_id,
+                names.host(&engagement.host_id),
+            )
+    }
+}
+
+fn feed_error(status: StatusCode, details: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("application/json")
+        .json(json!({
+            "error": "Calendar unavailable",
+            "details": details
+        }))
+}
+
+/// A subscribable calendar of engagements, filtered by any of language,
+/// instructor and host. Mounted outside the login requirement and
+/// authenticated by the read-only API token in its URL.
+#[get("/calendar/engagements.ics")]
+pub async fn calendar_feed(
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    tokens: Data<ApiTokenStore>,
+    query: Query<CalendarQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Subscription URLs end up stored by calendar apps and their sync
+    // services, so they must never carry a token that can change anything
+    let Some(token) = api_tokens::authenticate(&tokens, &query.token)?
+        .filter(|token| token.scope == TokenScope::ReadOnly)
+    else {
+        return Ok(feed_error(
+            StatusCode::UNAUTHORIZED,
+            "Calendar feeds need a valid read-only API token",
+        ));
+    };
+    let user = token.as_user();
+
+    if let Some(language) = &query.language {
+        if !user.can_access(language) {
+            return Ok(feed_error(
+                StatusCode::FORBIDDEN,
+                &format!("This token can't access {}", language),
+            ));
+        }
+    }
+
+    let names = roster.names()?;
+    let mut engagements: Vec<Engagement> = repo
+        .list()?
+        .into_iter()
+        .filter(|e| user.can_access(&e.language))
+        .filter(|e| query.matches(e, &names))
+        .collect();
+    engagements
+        .sort_by(|a, b| compare_engagement_dates(a, b).unwrap_or_else(|_| a.date.cmp(&b.date)));
+
+    let name = match &query.language {
+        Some(language) => format!("{} Engagements", language),
+        None => "Engagements".to_string(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(ical::calendar(&name, &engagements, &names)))
+}
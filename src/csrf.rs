@@ -0,0 +1,312 @@
+use actix_session::{Session, SessionExt};
+use actix_web::{
+    body::EitherBody,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::ErrorInternalServerError,
+    http::{header, Method},
+    web::BytesMut,
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use futures_util::{future::LocalBoxFuture, StreamExt};
+use serde_json::json;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use crate::api_tokens;
+
+/// Header `fetch` calls send the token in.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+/// Field HTML forms send the token in.
+const CSRF_FIELD: &str = "csrf_token";
+const CSRF_SESSION_KEY: &str = "csrf_token";
+// Largest form body buffered while looking for the token field
+const MAX_FORM_BYTES: usize = 64 * 1024;
+
+/// Returns the session's CSRF token, creating one if it doesn't have one yet.
+pub fn session_token(session: &Session) -> Result<String, Error> {
+    if let Some(token) = session
+        .get::<String>(CSRF_SESSION_KEY)
+        .map_err(ErrorInternalServerError)?
+    {
+        return Ok(token);
+    }
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    session
+        .insert(CSRF_SESSION_KEY, &token)
+        .map_err(ErrorInternalServerError)?;
+    Ok(token)
+}
+
+/// The current session's CSRF token, for templates to embed.
+pub struct CsrfToken(pub String);
+
+impl FromRequest for CsrfToken {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(session_token(&req.get_session()).map(CsrfToken))
+    }
+}
+
+fn tokens_match(a: &str, b: &str) -> bool {
+    // Constant time, so the comparison doesn't leak how much of a guess was right
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+// Tokens are hex, so the field value never needs percent-decoding
+fn form_token(body: &[u8]) -> Option<String> {
+    std::str::from_utf8(body)
+        .ok()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == CSRF_FIELD)
+        .map(|(_, value)| value.to_string())
+}
+
+fn is_form(req: &ServiceRequest) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"))
+}
+
+/// Rejects state-changing requests whose CSRF token, sent in the
+/// `X-CSRF-Token` header or a `csrf_token` form field, doesn't match the
+/// session's. Must be wrapped inside `SessionMiddleware`.
+pub struct CsrfProtection;
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CsrfProtectionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfProtectionMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct CsrfProtectionMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+                return Ok(service.call(req).await?.map_into_left_body());
+            }
+            // Browsers never attach bearer tokens on their own, so requests
+            // authenticated by one can't be forged from another site. Tokens
+            // are only accepted on the API, so nowhere else is exempt.
+            if api_tokens::accepts_tokens(req.path())
+                && api_tokens::bearer_token(&req).is_some()
+            {
+                return Ok(service.call(req).await?.map_into_left_body());
+            }
+
+            let expected = req
+                .get_session()
+                .get::<String>(CSRF_SESSION_KEY)
+                .ok()
+                .flatten();
+            let mut provided = req
+                .headers()
+                .get(CSRF_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+
+            if provided.is_none() && is_form(&req) {
+                // Read the form to find the token, then hand the same bytes
+                // back so the handler can still extract it
+                let mut payload = req.take_payload();
+                let mut body = BytesMut::new();
+                while let Some(chunk) = payload.next().await {
+                    body.extend_from_slice(&chunk?);
+                    // The rest can't be read back for the handler, so it
+                    // must not get a cut-off form
+                    if body.len() > MAX_FORM_BYTES {
+                        let (http_request, _payload) = req.into_parts();
+                        let response = HttpResponse::PayloadTooLarge()
+                            .content_type("application/json")
+                            .json(json!({
+                                "error": "Form too large",
+                                "details": format!(
+                                    "Forms can be at most {} KiB",
+                                    MAX_FORM_BYTES / 1024
+                                )
+                            }))
+                            .map_into_right_body();
+                        return Ok(ServiceResponse::new(http_request, response));
+                    }
+                }
+                provided = form_token(&body);
+
+                let (_, mut replay) = actix_http::h1::Payload::create(true);
+                replay.unread_data(body.freeze());
+                req.set_payload(replay.into());
+            }
+
+            match (expected, provided) {
+                (Some(expected), Some(provided)) if tokens_match(&expected, &provided) => {
+                    Ok(service.call(req).await?.map_into_left_body())
+                }
+                _ => {
+                    log::warn!(
+                        "Rejected {} {} with a missing or invalid CSRF token",
+                        req.method(),
+                        req.path()
+                    );
+                    let (http_request, _payload) = req.into_parts();
+                    let response = HttpResponse::Forbidden()
+                        .content_type("application/json")
+                        .json(json!({
+                            "error": "Invalid CSRF token",
+                            "details": "Reload the page and try again"
+                        }))
+                        .map_into_right_body();
+                    Ok(ServiceResponse::new(http_request, response))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{
+        cookie::{Cookie, Key},
+        http::StatusCode,
+        test::{call_service, init_service, read_body, TestRequest},
+        web, App,
+    };
+
+    #[test]
+    fn tokens_match_only_when_equal() {
+        assert!(tokens_match("abc123", "abc123"));
+        assert!(!tokens_match("abc123", "abc124"));
+        assert!(!tokens_match("abc123", "abc12"));
+        assert!(!tokens_match("", "a"));
+    }
+
+    #[test]
+    fn form_token_finds_the_field() {
+        assert_eq!(
+            form_token(b"name=x&csrf_token=ab12&other=y").as_deref(),
+            Some("ab12")
+        );
+        assert_eq!(form_token(b"csrf_tokenx=ab12"), None);
+        assert_eq!(form_token(b"name=x"), None);
+        assert_eq!(form_token(&[0xff, 0xfe]), None);
+    }
+
+    async fn token(session: Session) -> Result<String, Error> {
+        session_token(&session)
+    }
+
+    async fn echo(body: web::Bytes) -> HttpResponse {
+        HttpResponse::Ok().body(body)
+    }
+
+    macro_rules! app {
+        () => {
+            init_service(
+                App::new()
+                    .wrap(CsrfProtection)
+                    .wrap(SessionMiddleware::new(
+                        CookieSessionStore::default(),
+                        Key::generate(),
+                    ))
+                    .route("/token", web::get().to(token))
+                    .route("/submit", web::post().to(echo)),
+            )
+            .await
+        };
+    }
+
+    fn form(cookie: &Cookie<'_>, body: String) -> TestRequest {
+        TestRequest::post()
+            .uri("/submit")
+            .cookie(cookie.clone())
+            .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn forms_need_the_session_token() {
+        let app = app!();
+        let response = call_service(&app, TestRequest::get().uri("/token").to_request()).await;
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let token = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+
+        let body = format!("name=x&csrf_token={}", token);
+        let response = call_service(&app, form(&cookie, body.clone()).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // The handler still gets the whole form
+        assert_eq!(read_body(response).await, body.as_bytes());
+
+        let response =
+            call_service(&app, form(&cookie, "csrf_token=0000".into()).to_request()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/submit")
+                .cookie(cookie.clone())
+                .insert_header((CSRF_HEADER, token))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn oversized_forms_are_rejected_whole() {
+        let app = app!();
+        let response = call_service(&app, TestRequest::get().uri("/token").to_request()).await;
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let token = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+
+        let body = format!("csrf_token={}&notes={}", token, "x".repeat(MAX_FORM_BYTES));
+        let response = call_service(&app, form(&cookie, body).to_request()).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,668 @@
+use actix_web::{
+    error::ErrorInternalServerError,
+    get, post,
+    web::{Data, Path, Query},
+    HttpResponse,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::api::{
+    compare_engagement_dates, plain_text, Engagement, Language, NewEngagement, Status,
+};
+use crate::audit::{AuditAction, AuditLog, AuditRecord};
+use crate::auth::{CurrentUser, Role};
+use crate::hosts::{Host, NewHost};
+use crate::instructors::{Instructor, NewInstructor};
+use crate::revisions::RevisionStore;
+use crate::roster::{Roster, RosterNames};
+use crate::store::StoreError;
+use crate::types::{EngagementRepo, HostRepo, InstructorRepo};
+
+// A cell starting with one of these is run as a formula by spreadsheets
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Text as it should read in a spreadsheet. Cells a spreadsheet would run
+/// as a formula are prefixed with `'` to keep them plain text.
+fn export_text(stored: &str) -> String {
+    let text = plain_text(stored);
+    if text.starts_with(FORMULA_PREFIXES) {
+        format!("'{}", text)
+    } else {
+        text
+    }
+}
+
+/// Imported text in the form it's stored in, so it can be compared with
+/// stored names. Undoes the formula guard `export_text` adds.
+fn import_text(cell: &str) -> String {
+    let cell = cell.trim();
+    let cell = match cell.strip_prefix('\'') {
+        Some(rest) if rest.starts_with(FORMULA_PREFIXES) => rest,
+        _ => cell,
+    };
+    ammonia::clean(cell)
+}
+
+/// An engagement as it appears in a CSV file, with its instructor and host
+/// by name.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct EngagementRow {
+    /// Written on export for reference; imported rows always get a new id
+    #[serde(skip_deserializing)]
+    pub id: Option<Uuid>,
+    pub instructor: String,
+    pub host: String,
+    pub date: String,
+    pub language: Language,
+    pub title: String,
+    pub part: usize,
+    pub num_parts: usize,
+    pub status: Status,
+}
+
+impl EngagementRow {
+    fn new(engagement: Engagement, names: &RosterNames) -> Self {
+        Self {
+            id: Some(engagement.id),
+            instructor: export_text(names.instructor(&engagement.instructor_id)),
+            host: export_text(names.host(&engagement.host_id)),
+            date: export_text(&engagement.date),
+            language: engagement.language,
+            title: export_text(&engagement.title),
+            part: engagement.part,
+            num_parts: engagement.num_parts,
+            status: engagement.status,
+        }
+    }
+
+    /// The row with its text as it will be stored, so names match the
+    /// rosters' and the preview shows what a commit saves.
+    fn cleaned(self) -> Self {
+        Self {
+            instructor: import_text(&self.instructor),
+            host: import_text(&self.host),
+            date: import_text(&self.date),
+            title: import_text(&self.title),
+            ..self
+        }
+    }
+
+    /// The row with its stored text decoded, for showing it to people.
+    fn plain(&self) -> Self {
+        Self {
+            instructor: plain_text(&self.instructor),
+            host: plain_text(&self.host),
+            date: plain_text(&self.date),
+            title: plain_text(&self.title),
+            ..self.clone()
+        }
+    }
+
+    fn into_new_engagement(self, instructor_id: Uuid, host_id: Uuid) -> NewEngagement {
+        NewEngagement {
+            instructor_id,
+            host_id,
+            date: self.date,
+            language: self.language,
+            title: self.title,
+            part: self.part,
+            num_parts: self.num_parts,
+            status: self.status,
+        }
+    }
+}
+
+/// An instructor or host as written to CSV, with their languages in one
+/// column.
+#[derive(serde::Serialize)]
+struct RosterRow {
+    id: Uuid,
+    name: String,
+    email: String,
+    phone: String,
+    languages: String,
+    time_zone: String,
+    notes: String,
+    active: bool,
+}
+
+fn join_languages(languages: &[Language]) -> String {
+    languages
+        .iter()
+        .map(|l| l.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl From<Instructor> for RosterRow {
+    fn from(instructor: Instructor) -> Self {
+        Self {
+            id: instructor.id,
+            name: export_text(&instructor.name),
+            email: export_text(&instructor.email),
+            phone: export_text(&instructor.phone),
+            languages: join_languages(&instructor.languages),
+            time_zone: export_text(&instructor.time_zone),
+            notes: export_text(&instructor.notes),
+            active: instructor.active,
+        }
+    }
+}
+
+impl From<Host> for RosterRow {
+    fn from(host: Host) -> Self {
+        Self {
+            id: host.id,
+            name: export_text(&host.name),
+            email: export_text(&host.email),
+            phone: export_text(&host.phone),
+            languages: join_languages(&host.languages),
+            time_zone: export_text(&host.time_zone),
+            notes: export_text(&host.notes),
+            active: host.active,
+        }
+    }
+}
+
+/// One data row of an imported file, with the engagement it describes or
+/// the reason it can't be imported.
+#[derive(serde::Serialize)]
+pub struct ImportRow {
+    /// Line in the file, counting the header as line 1
+    pub line: u64,
+    /// Shown as plain text, though it's kept as it will be stored
+    #[serde(serialize_with = "plain_row")]
+    pub engagement: Option<EngagementRow>,
+    pub error: Option<String>,
+}
+
+fn plain_row<S: serde::Serializer>(
+    row: &Option<EngagementRow>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&row.as_ref().map(EngagementRow::plain), serializer)
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportReport {
+    pub committed: bool,
+    pub valid: usize,
+    pub invalid: usize,
+    pub rows: Vec<ImportRow>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportQuery {
+    /// Without this the import is only a preview and nothing is saved
+    #[serde(default)]
+    pub commit: bool,
+}
+
+fn csv_response(filename: &str, body: Vec<u8>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ))
+        .body(body)
+}
+
+fn write_engagements(
+    engagements: Vec<Engagement>,
+    names: &RosterNames,
+) -> Result<Vec<u8>, actix_web::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for engagement in engagements {
+        writer
+            .serialize(EngagementRow::new(engagement, names))
+            .map_err(ErrorInternalServerError)?;
+    }
+    writer.into_inner().map_err(ErrorInternalServerError)
+}
+
+fn write_roster(rows: Vec<RosterRow>) -> Result<Vec<u8>, actix_web::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row).map_err(ErrorInternalServerError)?;
+    }
+    writer.into_inner().map_err(ErrorInternalServerError)
+}
+
+fn sorted_by_date(mut engagements: Vec<Engagement>) -> Vec<Engagement> {
+    engagements
+        .sort_by(|a, b| compare_engagement_dates(a, b).unwrap_or_else(|_| a.date.cmp(&b.date)));
+    engagements
+}
+
+/// Every engagement the user can access, in one file.
+#[get("/export/engagements")]
+pub async fn export_engagements(
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Viewer)?;
+
+    let engagements: Vec<Engagement> = repo
+        .list()?
+        .into_iter()
+        .filter(|e| user.can_access(&e.language))
+        .collect();
+
+    Ok(csv_response(
+        "engagements.csv",
+        write_engagements(sorted_by_date(engagements), &roster.names()?)?,
+    ))
+}
+
+#[get("/export/engagements/{lang}")]
+pub async fn export_language_engagements(
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    lang: Path<Language>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Viewer)?;
+    user.require_language(&lang)?;
+
+    let engagements: Vec<Engagement> = repo
+        .list()?
+        .into_iter()
+        .filter(|e| e.language == *lang)
+        .collect();
+
+    Ok(csv_response(
+        &format!("engagements-{}.csv", lang.to_string().to_lowercase()),
+        write_engagements(sorted_by_date(engagements), &roster.names()?)?,
+    ))
+}
+
+#[get("/export/instructors")]
+pub async fn export_instructors(
+    repo: Data<InstructorRepo>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let mut instructors = repo.list()?;
+    instructors.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(csv_response(
+        "instructors.csv",
+        write_roster(instructors.into_iter().map(RosterRow::from).collect())?,
+    ))
+}
+
+#[get("/export/hosts")]
+pub async fn export_hosts(
+    repo: Data<HostRepo>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let mut hosts = repo.list()?;
+    hosts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(csv_response(
+        "hosts.csv",
+        write_roster(hosts.into_iter().map(RosterRow::from).collect())?,
+    ))
+}
+
+/// Parses and validates every row of an engagements file. Columns are
+/// matched by header name, so an exported file can be imported as is; its
+/// `id` column is ignored and every row becomes a new engagement. Names not
+/// on the rosters are fine, as they are added on commit, but inactive
+/// instructors and hosts can't be given new engagements.
+fn check_rows(
+    body: &str,
+    user: &CurrentUser,
+    instructors: &[Instructor],
+    hosts: &[Host],
+) -> Result<Vec<ImportRow>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+    let headers = reader.headers()?.clone();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let result = record
+            .deserialize::<EngagementRow>(Some(&headers))
+            .map_err(|e| match e.kind() {
+                csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                _ => e.to_string(),
+            })
+            .map(EngagementRow::cleaned)
+            .and_then(|engagement| {
+                // Ids are only known on commit, so just the other fields are checked
+                engagement
+                    .clone()
+                    .into_new_engagement(Uuid::nil(), Uuid::nil())
+                    .validate()?;
+                if !user.can_access(&engagement.language) {
+                    return Err(format!("You are not assigned to {}", engagement.language));
+                }
+                if engagement.instructor.is_empty() || engagement.host.is_empty() {
+                    return Err("Every engagement needs an instructor and a host".to_string());
+                }
+                if instructors
+                    .iter()
+                    .any(|i| i.name == engagement.instructor && !i.active)
+                {
+                    return Err(format!(
+                        "Instructor {} is inactive",
+                        plain_text(&engagement.instructor)
+                    ));
+                }
+                if hosts.iter().any(|h| h.name == engagement.host && !h.active) {
+                    return Err(format!("Host {} is inactive", plain_text(&engagement.host)));
+                }
+                Ok(engagement)
+            });
+
+        rows.push(match result {
+            Ok(engagement) => ImportRow {
+                line,
+                engagement: Some(engagement),
+                error: None,
+            },
+            Err(error) => ImportRow {
+                line,
+                engagement: None,
+                error: Some(error),
+            },
+        });
+    }
+    Ok(rows)
+}
+
+/// Everything an import has stored so far, so it can be taken back if a
+/// later row fails to save.
+#[derive(Default)]
+struct AddedRecords {
+    instructors: Vec<Instructor>,
+    hosts: Vec<Host>,
+    engagements: Vec<Engagement>,
+}
+
+impl AddedRecords {
+    /// Removes what was added, newest first. Failures are only logged, as the
+    /// error that started the rollback is the one to report.
+    fn roll_back(&self, repo: &EngagementRepo, roster: &Roster) {
+        for engagement in self.engagements.iter().rev() {
+            if let Err(e) = repo.remove(engagement.id, engagement.version) {
+                log::error!(
+                    "Failed to roll back imported engagement {}: {}",
+                    engagement.id,
+                    e
+                );
+            }
+        }
+        for host in self.hosts.iter().rev() {
+            if let Err(e) = roster.hosts.remove(host.id) {
+                log::error!("Failed to roll back imported host {}: {}", host.id, e);
+            }
+        }
+        for instructor in self.instructors.iter().rev() {
+            if let Err(e) = roster.instructors.remove(instructor.id) {
+                log::error!(
+                    "Failed to roll back imported instructor {}: {}",
+                    instructor.id,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Stores the engagements of checked rows, adding names that aren't on the
+/// rosters yet to them. Each record is added to `added` as it is stored.
+fn add_rows(
+    repo: &EngagementRepo,
+    roster: &Roster,
+    rows: &[ImportRow],
+    instructors: Vec<Instructor>,
+    hosts: Vec<Host>,
+    added: &mut AddedRecords,
+) -> Result<(), StoreError> {
+    let mut instructor_ids: HashMap<String, Uuid> =
+        instructors.into_iter().map(|i| (i.name, i.id)).collect();
+    let mut host_ids: HashMap<String, Uuid> = hosts.into_iter().map(|h| (h.name, h.id)).collect();
+
+    for row in rows.iter().filter_map(|row| row.engagement.clone()) {
+        let instructor_id = match instructor_ids.get(&row.instructor) {
+            Some(id) => *id,
+            None => {
+                let instructor = NewInstructor::named(&row.instructor).into_instructor();
+                let id = instructor.id;
+                roster.instructors.insert(instructor.clone())?;
+                instructor_ids.insert(row.instructor.clone(), id);
+                added.instructors.push(instructor);
+                id
+            }
+        };
+        let host_id = match host_ids.get(&row.host) {
+            Some(id) => *id,
+            None => {
+                let host = NewHost::named(&row.host).into_host();
+                let id = host.id;
+                roster.hosts.insert(host.clone())?;
+                host_ids.insert(row.host.clone(), id);
+                added.hosts.push(host);
+                id
+            }
+        };
+
+        let engagement = row
+            .into_new_engagement(instructor_id, host_id)
+            .into_engagement();
+        repo.insert(engagement.clone())?;
+        added.engagements.push(engagement);
+    }
+    Ok(())
+}
+
+/// Imports engagements from a CSV request body. By default this only
+/// previews the result; with `?commit=true` the rows are saved, provided
+/// every one of them is valid.
+#[post("/import/engagements")]
+pub async fn import_engagements(
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    revisions: Data<RevisionStore>,
+    audit: Data<AuditLog>,
+    query: Query<ImportQuery>,
+    body: String,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let instructors = roster.instructors.list()?;
+    let hosts = roster.hosts.list()?;
+    let rows = match check_rows(&body, &user, &instructors, &hosts) {
+        Ok(rows) => rows,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("application/json")
+                .json(json!({
+                    "error": "Invalid CSV",
+                    "details": e.to_string()
+                })))
+        }
+    };
+    if rows.is_empty() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Invalid CSV",
+                "details": "The file has no engagement rows"
+            })));
+    }
+
+    let invalid = rows.iter().filter(|row| row.error.is_some()).count();
+    let mut report = ImportReport {
+        committed: false,
+        valid: rows.len() - invalid,
+        invalid,
+        rows,
+    };
+
+    if !query.commit {
+        return Ok(HttpResponse::Ok()
+            .content_type("application/json; charset=utf-8")
+            .json(report));
+    }
+    if report.invalid > 0 {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("application/json; charset=utf-8")
+            .json(report));
+    }
+
+    let mut added = AddedRecords::default();
+    if let Err(e) = add_rows(&repo, &roster, &report.rows, instructors, hosts, &mut added) {
+        added.roll_back(&repo, &roster);
+        return Err(e.into());
+    }
+
+    // Only recorded once every row is stored, as until then the import
+    // could still be rolled back
+    for instructor in &added.instructors {
+        audit.record(
+            AuditRecord::new(&user.username, AuditAction::AddInstructor, instructor.id)
+                .after(instructor),
+        )?;
+    }
+    for host in &added.hosts {
+        audit
+            .record(AuditRecord::new(&user.username, AuditAction::AddHost, host.id).after(host))?;
+    }
+    for engagement in &added.engagements {
+        revisions.record_created(engagement, &user.username)?;
+        audit.record(
+            AuditRecord::new(&user.username, AuditAction::AddEngagement, engagement.id)
+                .after(engagement),
+        )?;
+    }
+    report.committed = true;
+    log::info!("{} imported {} engagements", user.username, report.valid);
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::User;
+    use crate::store::LocalStore;
+    use actix_web::{
+        dev::Service,
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        App, HttpMessage,
+    };
+    use std::sync::Arc;
+
+    fn admin() -> CurrentUser {
+        CurrentUser(User {
+            username: "admin".to_string(),
+            password_hash: String::new(),
+            disabled: false,
+            created_at: chrono::Utc::now(),
+            role: Role::Admin,
+            languages: Vec::new(),
+            two_factor: Default::default(),
+        })
+    }
+
+    #[test]
+    fn exported_text_is_unescaped() {
+        assert_eq!(export_text("Tom &amp; Jerry"), "Tom & Jerry");
+        assert_eq!(export_text("1 &lt; 2 &gt; 0"), "1 < 2 > 0");
+        assert_eq!(export_text("&amp;lt;"), "&lt;");
+    }
+
+    #[test]
+    fn formulas_are_exported_as_text() {
+        for cell in ["=1+1", "+1 555 0100", "-2", "@SUM(A1)", "\tx"] {
+            assert_eq!(export_text(cell), format!("'{}", cell));
+        }
+        assert_eq!(export_text("Ann's class"), "Ann's class");
+    }
+
+    #[test]
+    fn exported_text_imports_as_stored() {
+        for stored in ["Tom &amp; Jerry", "=1+1", "-5 &lt; 3", "'quoted'"] {
+            assert_eq!(import_text(&export_text(stored)), stored);
+        }
+    }
+
+    #[test]
+    fn imported_names_match_stored_ones() {
+        let instructor = Instructor {
+            active: false,
+            ..NewInstructor::named("Tom & Jerry").into_instructor()
+        };
+        assert_eq!(instructor.name, "Tom &amp; Jerry");
+
+        let body = "instructor,host,date,language,title,part,num_parts,status\n\
+                    Tom & Jerry,'=Hall,2024-05-01,English,A & B,1,1,Planning\n";
+        let rows = check_rows(body, &admin(), &[instructor], &[]).unwrap();
+        assert_eq!(
+            rows[0].error.as_deref(),
+            Some("Instructor Tom & Jerry is inactive")
+        );
+
+        let body = body.replace("Tom & Jerry", "Ann Lee");
+        let rows = check_rows(&body, &admin(), &[], &[]).unwrap();
+        let row = rows[0].engagement.as_ref().unwrap();
+        assert_eq!(row.host, "=Hall");
+        assert_eq!(row.title, "A &amp; B");
+    }
+
+    #[actix_web::test]
+    async fn failed_commits_leave_nothing_behind() {
+        let dir = std::env::temp_dir().join(format!("csv-test-{}", Uuid::new_v4()));
+        let store = Arc::new(LocalStore::open(dir.join("data")).unwrap());
+        // Saving engagements fails once the new instructor and host are saved
+        std::fs::create_dir(dir.join("data").join("engagements.json.tmp")).unwrap();
+        let repo = EngagementRepo::new(store.clone());
+        let roster = Roster::new(InstructorRepo::new(store.clone()), HostRepo::new(store));
+        let audit = AuditLog::open(dir.join("audit.jsonl")).unwrap();
+        let admin = admin().0;
+
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(repo.clone()))
+                .app_data(Data::new(roster.clone()))
+                .app_data(Data::new(
+                    RevisionStore::open(dir.join("revisions.json")).unwrap(),
+                ))
+                .app_data(Data::new(audit))
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(admin.clone());
+                    srv.call(req)
+                })
+                .service(import_engagements),
+        )
+        .await;
+        let body = "instructor,host,date,language,title,part,num_parts,status\n\
+                    Ann Lee,Hall,2024-05-01,English,Intro,1,1,Planning\n";
+        let req = TestRequest::post()
+            .uri("/import/engagements?commit=true")
+            .set_payload(body)
+            .to_request();
+        let response = call_service(&app, req).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(repo.list().unwrap().is_empty());
+        assert!(roster.instructors.list().unwrap().is_empty());
+        assert!(roster.hosts.list().unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,64 +1,270 @@
-
+use crate::api::Language;
+use crate::audit::{AuditAction, AuditLog, AuditRecord};
+use crate::auth::{CurrentUser, Role};
+use crate::types::{EngagementRepo, HostRepo, TrashRepo};
 use actix_web::{
-    delete, get, post,
-    web::{Data, Path},
+    delete, get, patch, post,
+    web::{Data, Json, Path},
     HttpResponse,
 };
+use serde_json::json;
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Host {
+    pub id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub phone: String,
+    /// Languages the host serves. Empty means any language, as for hosts
+    /// added before this was recorded.
+    pub languages: Vec<Language>,
+    /// IANA time zone name, e.g. "Europe/Madrid"
+    pub time_zone: String,
+    pub notes: String,
+    /// Inactive hosts stay on their engagements but can't be given new ones
+    pub active: bool,
+}
+
+impl Host {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Host name can't be empty".to_string());
+        }
+
+        if !self.email.is_empty() && !self.email.contains('@') {
+            return Err(format!("Invalid email address: {}", self.email));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the host can be picked for an engagement in `language`.
+    pub fn available_for(&self, language: &Language) -> bool {
+        self.active && (self.languages.is_empty() || self.languages.contains(language))
+    }
+
+    fn clean(&self) -> Self {
+        Self {
+            id: self.id,
+            name: ammonia::clean(self.name.trim()),
+            email: ammonia::clean(self.email.trim()),
+            phone: ammonia::clean(self.phone.trim()),
+            languages: self.languages.clone(),
+            time_zone: ammonia::clean(self.time_zone.trim()),
+            notes: ammonia::clean(&self.notes),
+            active: self.active,
+        }
+    }
+}
+
+impl std::hash::Hash for Host {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl PartialEq for Host {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Host {}
+
+fn active_by_default() -> bool {
+    true
+}
+
+/// A host to add. Only the name is required.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct NewHost {
+    pub name: String,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub phone: String,
+    #[serde(default)]
+    pub languages: Vec<Language>,
+    #[serde(default)]
+    pub time_zone: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default = "active_by_default")]
+    pub active: bool,
+}
+
+impl NewHost {
+    /// An active host known only by name, as added by an import.
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            email: String::new(),
+            phone: String::new(),
+            languages: Vec::new(),
+            time_zone: String::new(),
+            notes: String::new(),
+            active: true,
+        }
+    }
+
+    /// Builds the host to store, with a fresh id and sanitized text.
+    pub fn into_host(self) -> Host {
+        Host {
+            id: Uuid::new_v4(),
+            name: self.name,
+            email: self.email,
+            phone: self.phone,
+            languages: self.languages,
+            time_zone: self.time_zone,
+            notes: self.notes,
+            active: self.active,
+        }
+        .clean()
+    }
+}
+
+fn invalid(details: String) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Validation failed",
+            "details": details
+        }))
+}
 
-use crate::types::HostRepo;
+/// Names identify hosts in CSV files, so no two may share one.
+fn name_taken(repo: &HostRepo, host: &Host) -> Result<bool, actix_web::Error> {
+    Ok(repo
+        .list()?
+        .iter()
+        .any(|i| i.id != host.id && i.name == host.name))
+}
 
-#[post("/hosts/{new}")]
+#[post("/hosts")]
 pub async fn add_host(
     repo: Data<HostRepo>,
-    new: Path<String>,
+    audit: Data<AuditLog>,
+    body: Json<NewHost>,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let sanitized = ammonia::clean(&new);
+    user.require(Role::Scheduler)?;
 
-    match repo.lock() {
-        Ok(mut repo) => {
-            repo.insert(sanitized);
-            Ok(HttpResponse::Created().finish())
-        }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+    let host = body.into_inner().into_host();
+    if let Err(validation_error) = host.validate() {
+        return Ok(invalid(validation_error));
     }
+    if name_taken(&repo, &host)? {
+        return Ok(invalid(format!(
+            "There is already a host named {}",
+            host.name
+        )));
+    }
+
+    repo.insert(host.clone())?;
+    audit.record(
+        AuditRecord::new(&user.username, AuditAction::AddHost, host.id)
+            .after(&host),
+    )?;
+    Ok(HttpResponse::Created()
+        .content_type("application/json; charset=utf-8")
+        .json(host))
 }
 
 #[get("/hosts")]
-pub async fn get_hosts(repo: Data<HostRepo>) -> Result<HttpResponse, actix_web::Error> {
-    match repo.lock() {
-        Ok(repo) => {
-            let hosts: Vec<String> = repo.iter().cloned().collect();
-
-            Ok(HttpResponse::Ok()
-                .content_type("application/json; charset=utf-8")
-                .json(hosts))
-        }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+pub async fn get_hosts(
+    repo: Data<HostRepo>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let mut hosts: Vec<Host> = repo.list()?;
+    hosts.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(hosts))
+}
+
+#[patch("/hosts")]
+pub async fn edit_host(
+    repo: Data<HostRepo>,
+    audit: Data<AuditLog>,
+    body: Json<Host>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let host = body.into_inner().clean();
+    if let Err(validation_error) = host.validate() {
+        return Ok(invalid(validation_error));
+    }
+    let Some(current) = repo.list()?.into_iter().find(|i| i.id == host.id) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if name_taken(&repo, &host)? {
+        return Ok(invalid(format!(
+            "There is already a host named {}",
+            host.name
+        )));
+    }
+
+    if repo.update(host.clone())? {
+        audit.record(
+            AuditRecord::new(&user.username, AuditAction::EditHost, host.id)
+                .before(&current)
+                .after(&host),
+        )?;
+        Ok(HttpResponse::Ok()
+            .content_type("application/json; charset=utf-8")
+            .json(host))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
     }
 }
 
-#[delete("/hosts/{h}")]
+#[delete("/hosts/{id}")]
 pub async fn delete_host(
     repo: Data<HostRepo>,
-    h: Path<String>,
+    engagements: Data<EngagementRepo>,
+    trash: Data<TrashRepo>,
+    audit: Data<AuditLog>,
+    id: Path<Uuid>,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let sanitized = ammonia::clean(&h);
-
-    match repo.lock() {
-        Ok(mut repo) => {
-            if repo.contains(&sanitized) {
-                repo.remove(&sanitized);
-                Ok(HttpResponse::Ok().finish())
-            } else {
-                Ok(HttpResponse::NotFound().finish())
-            }
-        }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+    user.require(Role::Admin)?;
+
+    // Engagements, including restorable ones, must never point at a missing host
+    let assigned = engagements
+        .list()?
+        .iter()
+        .filter(|e| e.host_id == *id)
+        .count()
+        + trash
+            .list()?
+            .iter()
+            .filter(|t| t.engagement.host_id == *id)
+            .count();
+    if assigned > 0 {
+        return Ok(HttpResponse::Conflict()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Host in use",
+                "details": format!(
+                    "This host is assigned to {} engagements, including any in the trash. \
+                     Mark them as inactive instead.",
+                    assigned
+                )
+            })));
+    }
+
+    if let Some(removed) = repo.remove(*id)? {
+        audit.record(
+            AuditRecord::new(&user.username, AuditAction::DeleteHost, removed.id)
+                .before(&removed),
+        )?;
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
     }
 }
@@ -0,0 +1,213 @@
+use chrono::{NaiveDate, Utc};
+
+use crate::api::{plain_text, Engagement, Status};
+use crate::roster::RosterNames;
+
+const PRODID: &str = "-//Admin Panel//Engagements//EN";
+// Content lines longer than this many octets have to be folded (RFC 5545 3.1)
+const MAX_LINE_OCTETS: usize = 75;
+
+/// Escapes a TEXT value (RFC 5545 3.3.11).
+fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends `line` to `out`, folded so no line exceeds `MAX_LINE_OCTETS`
+/// and without splitting a UTF-8 character.
+fn push_line(out: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        // Continuation lines start with a space, which counts towards the limit
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            out.push_str("\r\n ");
+            octets = 1;
+        }
+        out.push(c);
+        octets += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn event_status(status: &Status) -> &'static str {
+    match status {
+        Status::Planning | Status::Invited => "TENTATIVE",
+        Status::Confirmed => "CONFIRMED",
+    }
+}
+
+fn summary(engagement: &Engagement) -> String {
+    let title = plain_text(&engagement.title);
+    if engagement.num_parts > 1 {
+        format!(
+            "{} (Part {} of {})",
+            title, engagement.part, engagement.num_parts
+        )
+    } else {
+        title
+    }
+}
+
+/// Renders `engagements` as an RFC 5545 calendar of all-day events.
+/// Each event's UID is the engagement id, so calendar apps update events in
+/// place when an engagement changes instead of adding a duplicate.
+pub fn calendar(name: &str, engagements: &[Engagement], names: &RosterNames) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut out = String::new();
+
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, &format!("PRODID:{}", PRODID));
+    push_line(&mut out, "CALSCALE:GREGORIAN");
+    push_line(&mut out, "METHOD:PUBLISH");
+    push_line(&mut out, &format!("X-WR-CALNAME:{}", escape_text(name)));
+
+    for engagement in engagements {
+        let Ok(date) = NaiveDate::parse_from_str(&engagement.date, "%Y-%m-%d") else {
+            log::warn!(
+                "Leaving engagement {} out of calendar: invalid date '{}'",
+                engagement.id,
+                engagement.date
+            );
+            continue;
+        };
+        let end = date.succ_opt().unwrap_or(date);
+
+        push_line(&mut out, "BEGIN:VEVENT");
+        push_line(&mut out, &format!("UID:{}@admin-panel", engagement.id));
+        push_line(&mut out, &format!("DTSTAMP:{}", stamp));
+        push_line(
+            &mut out,
+            &format!("DTSTART;VALUE=DATE:{}", date.format("%Y%m%d")),
+        );
+        push_line(
+            &mut out,
+            &format!("DTEND;VALUE=DATE:{}", end.format("%Y%m%d")),
+        );
+        push_line(
+            &mut out,
+            &format!("SUMMARY:{}", escape_text(&summary(engagement))),
+        );
+        push_line(
+            &mut out,
+            &format!(
+                "DESCRIPTION:{}",
+                escape_text(&format!(
+                    "Instructor: {}\nHost: {}\nLanguage: {}\nStatus: {}",
+                    plain_text(names.instructor(&engagement.instructor_id)),
+                    plain_text(names.host(&engagement.host_id)),
+                    engagement.language,
+                    engagement.status
+                ))
+            ),
+        );
+        push_line(
+            &mut out,
+            &format!("STATUS:{}", event_status(&engagement.status)),
+        );
+        push_line(&mut out, "END:VEVENT");
+    }
+
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Language, NewEngagement};
+    use uuid::Uuid;
+
+    fn unfold(folded: &str) -> String {
+        folded.replace("\r\n ", "")
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(escape_text("a\\b;c,d\r\ne"), r"a\\b\;c\,d\ne");
+        assert_eq!(escape_text("Plain: text"), "Plain: text");
+    }
+
+    #[test]
+    fn long_lines_are_folded() {
+        for line in [
+            "x".repeat(200),
+            "é".repeat(100),
+            format!("a{}", "€".repeat(60)),
+        ] {
+            let mut out = String::new();
+            push_line(&mut out, &line);
+
+            assert!(out.ends_with("\r\n"));
+            for folded in out.trim_end_matches("\r\n").split("\r\n") {
+                assert!(folded.len() <= MAX_LINE_OCTETS, "{} octets", folded.len());
+            }
+            assert_eq!(unfold(&out), format!("{}\r\n", line));
+        }
+
+        let mut out = String::new();
+        push_line(&mut out, &"x".repeat(MAX_LINE_OCTETS));
+        assert_eq!(out, format!("{}\r\n", "x".repeat(MAX_LINE_OCTETS)));
+    }
+
+    #[test]
+    fn calendar_has_an_event_per_valid_engagement() {
+        let engagement = |date: &str| {
+            NewEngagement {
+                instructor_id: Uuid::new_v4(),
+                host_id: Uuid::new_v4(),
+                date: date.to_string(),
+                language: Language::English,
+                title: "Intro, part; one".to_string(),
+                part: 1,
+                num_parts: 2,
+                status: Status::Confirmed,
+            }
+            .into_engagement()
+        };
+        let valid = engagement("2024-05-31");
+        let feed = calendar(
+            "Team",
+            &[valid.clone(), engagement("someday")],
+            &RosterNames::default(),
+        );
+        let feed = unfold(&feed);
+
+        assert_eq!(feed.matches("BEGIN:VEVENT").count(), 1);
+        assert!(feed.contains(&format!("UID:{}@admin-panel\r\n", valid.id)));
+        assert!(feed.contains("DTSTART;VALUE=DATE:20240531\r\n"));
+        assert!(feed.contains("DTEND;VALUE=DATE:20240601\r\n"));
+        assert!(feed.contains("SUMMARY:Intro\\, part\\; one (Part 1 of 2)\r\n"));
+        assert!(feed.contains("STATUS:CONFIRMED\r\n"));
+    }
+
+    #[test]
+    fn stored_entities_are_decoded() {
+        let engagement = NewEngagement {
+            instructor_id: Uuid::new_v4(),
+            host_id: Uuid::new_v4(),
+            date: "2024-05-31".to_string(),
+            language: Language::English,
+            title: "Tom & Jerry".to_string(),
+            part: 1,
+            num_parts: 1,
+            status: Status::Planning,
+        }
+        .into_engagement();
+        assert_eq!(engagement.title, "Tom &amp; Jerry");
+
+        let feed = unfold(&calendar("Team", &[engagement], &RosterNames::default()));
+        assert!(feed.contains("SUMMARY:Tom & Jerry\r\n"), "{}", feed);
+        assert!(!feed.contains("&amp;"));
+    }
+}
@@ -1,63 +1,271 @@
-
-use crate::types::InstructorRepo;
+use crate::api::Language;
+use crate::audit::{AuditAction, AuditLog, AuditRecord};
+use crate::auth::{CurrentUser, Role};
+use crate::types::{EngagementRepo, InstructorRepo, TrashRepo};
 use actix_web::{
-    delete, get, post,
-    web::{Data, Path},
+    delete, get, patch, post,
+    web::{Data, Json, Path},
     HttpResponse,
 };
+use serde_json::json;
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Instructor {
+    pub id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub phone: String,
+    /// Languages the instructor teaches in. Empty means any language, as
+    /// for instructors added before this was recorded.
+    pub languages: Vec<Language>,
+    /// IANA time zone name, e.g. "Europe/Madrid"
+    pub time_zone: String,
+    pub notes: String,
+    /// Inactive instructors stay on their engagements but can't be given new ones
+    pub active: bool,
+}
+
+impl Instructor {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Instructor name can't be empty".to_string());
+        }
+
+        if !self.email.is_empty() && !self.email.contains('@') {
+            return Err(format!("Invalid email address: {}", self.email));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the instructor can be picked for an engagement in `language`.
+    pub fn available_for(&self, language: &Language) -> bool {
+        self.active && (self.languages.is_empty() || self.languages.contains(language))
+    }
+
+    fn clean(&self) -> Self {
+        Self {
+            id: self.id,
+            name: ammonia::clean(self.name.trim()),
+            email: ammonia::clean(self.email.trim()),
+            phone: ammonia::clean(self.phone.trim()),
+            languages: self.languages.clone(),
+            time_zone: ammonia::clean(self.time_zone.trim()),
+            notes: ammonia::clean(&self.notes),
+            active: self.active,
+        }
+    }
+}
+
+impl std::hash::Hash for Instructor {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl PartialEq for Instructor {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Instructor {}
+
+fn active_by_default() -> bool {
+    true
+}
+
+/// An instructor to add. Only the name is required.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct NewInstructor {
+    pub name: String,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub phone: String,
+    #[serde(default)]
+    pub languages: Vec<Language>,
+    #[serde(default)]
+    pub time_zone: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default = "active_by_default")]
+    pub active: bool,
+}
+
+impl NewInstructor {
+    /// An active instructor known only by name, as added by an import.
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            email: String::new(),
+            phone: String::new(),
+            languages: Vec::new(),
+            time_zone: String::new(),
+            notes: String::new(),
+            active: true,
+        }
+    }
 
-#[post("/instructors/{new}")]
+    /// Builds the instructor to store, with a fresh id and sanitized text.
+    pub fn into_instructor(self) -> Instructor {
+        Instructor {
+            id: Uuid::new_v4(),
+            name: self.name,
+            email: self.email,
+            phone: self.phone,
+            languages: self.languages,
+            time_zone: self.time_zone,
+            notes: self.notes,
+            active: self.active,
+        }
+        .clean()
+    }
+}
+
+fn invalid(details: String) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Validation failed",
+            "details": details
+        }))
+}
+
+/// Names identify instructors in CSV files, so no two may share one.
+fn name_taken(repo: &InstructorRepo, instructor: &Instructor) -> Result<bool, actix_web::Error> {
+    Ok(repo
+        .list()?
+        .iter()
+        .any(|i| i.id != instructor.id && i.name == instructor.name))
+}
+
+#[post("/instructors")]
 pub async fn add_instructor(
     repo: Data<InstructorRepo>,
-    new: Path<String>,
+    audit: Data<AuditLog>,
+    body: Json<NewInstructor>,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let sanitized = ammonia::clean(&new);
+    user.require(Role::Scheduler)?;
 
-    match repo.lock() {
-        Ok(mut repo) => {
-            repo.insert(sanitized);
-            Ok(HttpResponse::Created().finish())
-        }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+    let instructor = body.into_inner().into_instructor();
+    if let Err(validation_error) = instructor.validate() {
+        return Ok(invalid(validation_error));
     }
+    if name_taken(&repo, &instructor)? {
+        return Ok(invalid(format!(
+            "There is already an instructor named {}",
+            instructor.name
+        )));
+    }
+
+    repo.insert(instructor.clone())?;
+    audit.record(
+        AuditRecord::new(&user.username, AuditAction::AddInstructor, instructor.id)
+            .after(&instructor),
+    )?;
+    Ok(HttpResponse::Created()
+        .content_type("application/json; charset=utf-8")
+        .json(instructor))
 }
 
 #[get("/instructors")]
-pub async fn get_instructors(repo: Data<InstructorRepo>) -> Result<HttpResponse, actix_web::Error> {
-    match repo.lock() {
-        Ok(repo) => {
-            let instructors: Vec<String> = repo.iter().cloned().collect();
-
-            Ok(HttpResponse::Ok()
-                .content_type("application/json; charset=utf-8")
-                .json(instructors))
-        }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+pub async fn get_instructors(
+    repo: Data<InstructorRepo>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let mut instructors: Vec<Instructor> = repo.list()?;
+    instructors.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(instructors))
+}
+
+#[patch("/instructors")]
+pub async fn edit_instructor(
+    repo: Data<InstructorRepo>,
+    audit: Data<AuditLog>,
+    body: Json<Instructor>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let instructor = body.into_inner().clean();
+    if let Err(validation_error) = instructor.validate() {
+        return Ok(invalid(validation_error));
+    }
+    let Some(current) = repo.list()?.into_iter().find(|i| i.id == instructor.id) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if name_taken(&repo, &instructor)? {
+        return Ok(invalid(format!(
+            "There is already an instructor named {}",
+            instructor.name
+        )));
+    }
+
+    if repo.update(instructor.clone())? {
+        audit.record(
+            AuditRecord::new(&user.username, AuditAction::EditInstructor, instructor.id)
+                .before(&current)
+                .after(&instructor),
+        )?;
+        Ok(HttpResponse::Ok()
+            .content_type("application/json; charset=utf-8")
+            .json(instructor))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
     }
 }
 
-#[delete("/instructors/{i}")]
+#[delete("/instructors/{id}")]
 pub async fn delete_instructor(
     repo: Data<InstructorRepo>,
-    i: Path<String>,
+    engagements: Data<EngagementRepo>,
+    trash: Data<TrashRepo>,
+    audit: Data<AuditLog>,
+    id: Path<Uuid>,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let sanitized = ammonia::clean(&i);
-
-    match repo.lock() {
-        Ok(mut repo) => {
-            if repo.contains(&sanitized) {
-                repo.remove(&sanitized);
-                Ok(HttpResponse::Ok().finish())
-            } else {
-                Ok(HttpResponse::NotFound().finish())
-            }
-        }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
+    user.require(Role::Admin)?;
+
+    // Engagements, including restorable ones, must never point at a missing
+    // instructor
+    let assigned = engagements
+        .list()?
+        .iter()
+        .filter(|e| e.instructor_id == *id)
+        .count()
+        + trash
+            .list()?
+            .iter()
+            .filter(|t| t.engagement.instructor_id == *id)
+            .count();
+    if assigned > 0 {
+        return Ok(HttpResponse::Conflict()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Instructor in use",
+                "details": format!(
+                    "This instructor is assigned to {} engagements, including any in the trash. \
+                     Mark them as inactive instead.",
+                    assigned
+                )
+            })));
+    }
+
+    if let Some(removed) = repo.remove(*id)? {
+        audit.record(
+            AuditRecord::new(&user.username, AuditAction::DeleteInstructor, removed.id)
+                .before(&removed),
+        )?;
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
     }
 }
@@ -0,0 +1,574 @@
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+use uuid::Uuid;
+
+use crate::api::{Engagement, TrashedEngagement};
+use crate::hosts::Host;
+use crate::instructors::Instructor;
+use crate::store::{
+    EngagementStore, HostStore, InstructorStore, StoreError, TrashStore, VersionedRemove,
+    VersionedUpdate,
+};
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(tag = "op", content = "data")]
+pub enum Mutation {
+    AddEngagement(Engagement),
+    EditEngagement(Engagement),
+    DeleteEngagement(Uuid),
+    TrashEngagement(TrashedEngagement),
+    RemoveFromTrash(Uuid),
+    AddInstructor(Instructor),
+    EditInstructor(Instructor),
+    DeleteInstructor(Uuid),
+    AddHost(Host),
+    EditHost(Host),
+    DeleteHost(Uuid),
+    ReplaceEngagements(HashSet<Engagement>),
+    ReplaceTrash(HashSet<TrashedEngagement>),
+    ReplaceInstructors(HashSet<Instructor>),
+    ReplaceHosts(HashSet<Host>),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct JournalEntry {
+    pub timestamp: DateTime<Utc>,
+    pub mutation: Mutation,
+}
+
+struct JournalState {
+    file: File,
+    len: usize,
+    /// Off when there are no backups to replay entries onto
+    enabled: bool,
+}
+
+/// Exclusive access to the journal, from `Journal::lock`.
+pub struct JournalGuard<'a> {
+    state: MutexGuard<'a, JournalState>,
+}
+
+impl JournalGuard<'_> {
+    pub fn append(&mut self, mutation: Mutation) -> Result<(), StoreError> {
+        if !self.state.enabled {
+            return Ok(());
+        }
+        let entry = JournalEntry {
+            timestamp: Utc::now(),
+            mutation,
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        self.state.file.write_all(line.as_bytes())?;
+        self.state.file.sync_data()?;
+        self.state.len += 1;
+        Ok(())
+    }
+}
+
+/// Append-only log of every mutation made since the last successful backup,
+/// stored as one JSON entry per line. Each backup truncates it, so it's
+/// disabled when backups aren't configured rather than left to grow.
+pub struct Journal {
+    path: PathBuf,
+    state: Mutex<JournalState>,
+}
+
+impl Journal {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        drop_torn_line(&file)?;
+        let len = read_entries(&path)?.len();
+
+        Ok(Self {
+            path,
+            state: Mutex::new(JournalState {
+                file,
+                len,
+                enabled: true,
+            }),
+        })
+    }
+
+    /// Locks the journal for a change to the store. Holding the lock while the
+    /// change is applied and journaled keeps entries in the order their
+    /// changes reached the store, and keeps `len` from counting a change that
+    /// a snapshot taken afterwards could miss.
+    pub fn lock(&self) -> Result<JournalGuard<'_>, StoreError> {
+        Ok(JournalGuard {
+            state: self.state.lock().map_err(|_| StoreError::Lock)?,
+        })
+    }
+
+    /// Empties the journal and stops recording changes. Without backups
+    /// nothing would replay or truncate it, and the live files already hold
+    /// every change it would record.
+    pub fn disable(&self) -> Result<(), StoreError> {
+        let mut state = self.state.lock().map_err(|_| StoreError::Lock)?;
+        state.file.set_len(0)?;
+        state.file.sync_all()?;
+        state.len = 0;
+        state.enabled = false;
+        Ok(())
+    }
+
+    /// Number of entries currently in the journal.
+    pub fn len(&self) -> Result<usize, StoreError> {
+        Ok(self.state.lock().map_err(|_| StoreError::Lock)?.len)
+    }
+
+    pub fn entries(&self) -> Result<Vec<JournalEntry>, StoreError> {
+        let _state = self.state.lock().map_err(|_| StoreError::Lock)?;
+        read_entries(&self.path)
+    }
+
+    /// Drops the first `count` entries, keeping anything appended after a
+    /// backup snapshot was taken.
+    pub fn truncate_through(&self, count: usize) -> Result<(), StoreError> {
+        let mut state = self.state.lock().map_err(|_| StoreError::Lock)?;
+        let remaining: Vec<JournalEntry> = read_entries(&self.path)?
+            .into_iter()
+            .skip(count)
+            .collect();
+
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            for entry in &remaining {
+                serde_json::to_writer(&mut file, entry)?;
+                file.write_all(b"\n")?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+
+        state.file = OpenOptions::new().append(true).open(&self.path)?;
+        state.len = remaining.len();
+        Ok(())
+    }
+
+    /// Applies every journaled mutation, in order, on top of a restored snapshot.
+    pub fn replay(
+        &self,
+        engagements: &mut HashSet<Engagement>,
+        trash: &mut HashSet<TrashedEngagement>,
+        instructors: &mut HashSet<Instructor>,
+        hosts: &mut HashSet<Host>,
+    ) -> Result<usize, StoreError> {
+        let entries = self.entries()?;
+        for entry in &entries {
+            match &entry.mutation {
+                Mutation::AddEngagement(eng) | Mutation::EditEngagement(eng) => {
+                    engagements.replace(eng.clone());
+                }
+                Mutation::DeleteEngagement(id) => {
+                    engagements.retain(|e| e.id != *id);
+                }
+                Mutation::TrashEngagement(trashed) => {
+                    trash.replace(trashed.clone());
+                }
+                Mutation::RemoveFromTrash(id) => {
+                    trash.retain(|t| t.engagement.id != *id);
+                }
+                Mutation::AddInstructor(instructor) | Mutation::EditInstructor(instructor) => {
+                    instructors.replace(instructor.clone());
+                }
+                Mutation::DeleteInstructor(id) => {
+                    instructors.retain(|i| i.id != *id);
+                }
+                Mutation::AddHost(host) | Mutation::EditHost(host) => {
+                    hosts.replace(host.clone());
+                }
+                Mutation::DeleteHost(id) => {
+                    hosts.retain(|h| h.id != *id);
+                }
+                Mutation::ReplaceEngagements(all) => *engagements = all.clone(),
+                Mutation::ReplaceTrash(all) => *trash = all.clone(),
+                Mutation::ReplaceInstructors(all) => *instructors = all.clone(),
+                Mutation::ReplaceHosts(all) => *hosts = all.clone(),
+            }
+        }
+        Ok(entries.len())
+    }
+}
+
+/// Cuts off a final line left incomplete by a crash mid-append, so the next
+/// entry starts on a line of its own. The append that wrote it never
+/// finished, so the change it recorded was never reported as made.
+fn drop_torn_line(file: &File) -> Result<(), StoreError> {
+    let mut contents = Vec::new();
+    (&*file).read_to_end(&mut contents)?;
+    if contents.is_empty() || contents.ends_with(b"\n") {
+        return Ok(());
+    }
+
+    let keep = contents
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |newline| newline + 1);
+    log::warn!(
+        "Dropping {} bytes of an incomplete journal entry",
+        contents.len() - keep
+    );
+    file.set_len(keep as u64)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn read_entries(path: &Path) -> Result<Vec<JournalEntry>, StoreError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => log::warn!("Skipping unreadable journal entry: {}", e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Store wrapper that records every mutation in the journal once the store
+/// has applied it. The journal stays locked from the change until its entry
+/// is written, so entries keep the order their changes reached the store and
+/// a change that fails is never journaled for replay.
+pub struct JournaledStore<S> {
+    inner: Arc<S>,
+    journal: Arc<Journal>,
+}
+
+impl<S> JournaledStore<S> {
+    pub fn new(inner: Arc<S>, journal: Arc<Journal>) -> Self {
+        Self { inner, journal }
+    }
+}
+
+impl<S: EngagementStore> EngagementStore for JournaledStore<S> {
+    fn list(&self) -> Result<Vec<Engagement>, StoreError> {
+        self.inner.list()
+    }
+
+    fn insert(&self, engagement: Engagement) -> Result<(), StoreError> {
+        let mut journal = self.journal.lock()?;
+        self.inner.insert(engagement.clone())?;
+        journal.append(Mutation::AddEngagement(engagement))
+    }
+
+    fn update(
+        &self,
+        engagement: Engagement,
+        expected_version: u64,
+    ) -> Result<VersionedUpdate, StoreError> {
+        let mut journal = self.journal.lock()?;
+        let outcome = self.inner.update(engagement, expected_version)?;
+        if let VersionedUpdate::Updated(updated) = &outcome {
+            journal.append(Mutation::EditEngagement(updated.clone()))?;
+        }
+        Ok(outcome)
+    }
+
+    fn remove(&self, id: Uuid, expected_version: u64) -> Result<VersionedRemove, StoreError> {
+        let mut journal = self.journal.lock()?;
+        let outcome = self.inner.remove(id, expected_version)?;
+        if matches!(outcome, VersionedRemove::Removed(_)) {
+            journal.append(Mutation::DeleteEngagement(id))?;
+        }
+        Ok(outcome)
+    }
+
+    fn replace_all(&self, engagements: HashSet<Engagement>) -> Result<(), StoreError> {
+        let mut journal = self.journal.lock()?;
+        self.inner.replace_all(engagements.clone())?;
+        journal.append(Mutation::ReplaceEngagements(engagements))
+    }
+}
+
+impl<S: TrashStore> TrashStore for JournaledStore<S> {
+    fn list(&self) -> Result<Vec<TrashedEngagement>, StoreError> {
+        self.inner.list()
+    }
+
+    fn insert(&self, trashed: TrashedEngagement) -> Result<(), StoreError> {
+        let mut journal = self.journal.lock()?;
+        self.inner.insert(trashed.clone())?;
+        journal.append(Mutation::TrashEngagement(trashed))
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<TrashedEngagement>, StoreError> {
+        let mut journal = self.journal.lock()?;
+        let removed = self.inner.remove(id)?;
+        if removed.is_some() {
+            journal.append(Mutation::RemoveFromTrash(id))?;
+        }
+        Ok(removed)
+    }
+
+    fn replace_all(&self, trash: HashSet<TrashedEngagement>) -> Result<(), StoreError> {
+        let mut journal = self.journal.lock()?;
+        self.inner.replace_all(trash.clone())?;
+        journal.append(Mutation::ReplaceTrash(trash))
+    }
+}
+
+impl<S: InstructorStore> InstructorStore for JournaledStore<S> {
+    fn list(&self) -> Result<Vec<Instructor>, StoreError> {
+        self.inner.list()
+    }
+
+    fn insert(&self, instructor: Instructor) -> Result<(), StoreError> {
+        let mut journal = self.journal.lock()?;
+        self.inner.insert(instructor.clone())?;
+        journal.append(Mutation::AddInstructor(instructor))
+    }
+
+    fn update(&self, instructor: Instructor) -> Result<bool, StoreError> {
+        let mut journal = self.journal.lock()?;
+        let updated = self.inner.update(instructor.clone())?;
+        if updated {
+            journal.append(Mutation::EditInstructor(instructor))?;
+        }
+        Ok(updated)
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<Instructor>, StoreError> {
+        let mut journal = self.journal.lock()?;
+        let removed = self.inner.remove(id)?;
+        if removed.is_some() {
+            journal.append(Mutation::DeleteInstructor(id))?;
+        }
+        Ok(removed)
+    }
+
+    fn replace_all(&self, instructors: HashSet<Instructor>) -> Result<(), StoreError> {
+        let mut journal = self.journal.lock()?;
+        self.inner.replace_all(instructors.clone())?;
+        journal.append(Mutation::ReplaceInstructors(instructors))
+    }
+}
+
+impl<S: HostStore> HostStore for JournaledStore<S> {
+    fn list(&self) -> Result<Vec<Host>, StoreError> {
+        self.inner.list()
+    }
+
+    fn insert(&self, host: Host) -> Result<(), StoreError> {
+        let mut journal = self.journal.lock()?;
+        self.inner.insert(host.clone())?;
+        journal.append(Mutation::AddHost(host))
+    }
+
+    fn update(&self, host: Host) -> Result<bool, StoreError> {
+        let mut journal = self.journal.lock()?;
+        let updated = self.inner.update(host.clone())?;
+        if updated {
+            journal.append(Mutation::EditHost(host))?;
+        }
+        Ok(updated)
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<Host>, StoreError> {
+        let mut journal = self.journal.lock()?;
+        let removed = self.inner.remove(id)?;
+        if removed.is_some() {
+            journal.append(Mutation::DeleteHost(id))?;
+        }
+        Ok(removed)
+    }
+
+    fn replace_all(&self, hosts: HashSet<Host>) -> Result<(), StoreError> {
+        let mut journal = self.journal.lock()?;
+        self.inner.replace_all(hosts.clone())?;
+        journal.append(Mutation::ReplaceHosts(hosts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Language, NewEngagement, Status};
+    use crate::store::LocalStore;
+
+    fn journaled() -> (JournaledStore<LocalStore>, Arc<Journal>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("journal-test-{}", Uuid::new_v4()));
+        let journal = Arc::new(Journal::open(dir.join("journal.jsonl")).unwrap());
+        let store = JournaledStore::new(Arc::new(LocalStore::in_memory()), journal.clone());
+        (store, journal, dir)
+    }
+
+    fn engagement(title: &str) -> Engagement {
+        NewEngagement {
+            instructor_id: Uuid::new_v4(),
+            host_id: Uuid::new_v4(),
+            date: "2024-05-01".to_string(),
+            language: Language::English,
+            title: title.to_string(),
+            part: 1,
+            num_parts: 1,
+            status: Status::Planning,
+        }
+        .into_engagement()
+    }
+
+    fn replayed(journal: &Journal) -> HashSet<Engagement> {
+        let mut engagements = HashSet::new();
+        journal
+            .replay(
+                &mut engagements,
+                &mut HashSet::new(),
+                &mut HashSet::new(),
+                &mut HashSet::new(),
+            )
+            .unwrap();
+        engagements
+    }
+
+    #[test]
+    fn replay_reproduces_the_store() {
+        let (store, journal, dir) = journaled();
+        let first = engagement("First");
+        let second = engagement("Second");
+        EngagementStore::insert(&store, first.clone()).unwrap();
+        EngagementStore::insert(&store, second.clone()).unwrap();
+        let edited = Engagement {
+            title: "First, edited".to_string(),
+            ..first.clone()
+        };
+        EngagementStore::update(&store, edited, first.version).unwrap();
+        EngagementStore::remove(&store, second.id, second.version).unwrap();
+
+        let replayed = replayed(&journal);
+        let stored = EngagementStore::list(&store).unwrap();
+        assert_eq!(replayed.len(), 1);
+        let only = replayed.iter().next().unwrap();
+        assert_eq!(only.title, "First, edited");
+        assert_eq!(only.version, first.version + 1);
+        assert_eq!(stored[0].version, only.version);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejected_changes_are_not_journaled() {
+        let (store, journal, dir) = journaled();
+        let first = engagement("First");
+        EngagementStore::insert(&store, first.clone()).unwrap();
+
+        let stale = first.version + 5;
+        assert!(matches!(
+            EngagementStore::update(&store, first.clone(), stale).unwrap(),
+            VersionedUpdate::Conflict(_)
+        ));
+        assert!(matches!(
+            EngagementStore::remove(&store, first.id, stale).unwrap(),
+            VersionedRemove::Conflict(_)
+        ));
+        assert!(matches!(
+            EngagementStore::remove(&store, Uuid::new_v4(), 1).unwrap(),
+            VersionedRemove::NotFound
+        ));
+        assert_eq!(journal.len().unwrap(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restores_are_journaled() {
+        let (store, journal, dir) = journaled();
+        EngagementStore::insert(&store, engagement("Before restore")).unwrap();
+        let restored = engagement("Restored");
+        EngagementStore::replace_all(&store, HashSet::from([restored.clone()])).unwrap();
+
+        let replayed = replayed(&journal);
+        assert_eq!(replayed.len(), 1);
+        assert!(replayed.contains(&restored));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncation_keeps_later_entries() {
+        let (store, journal, dir) = journaled();
+        EngagementStore::insert(&store, engagement("Backed up")).unwrap();
+        let covered = journal.len().unwrap();
+        let later = engagement("After the backup");
+        EngagementStore::insert(&store, later.clone()).unwrap();
+
+        journal.truncate_through(covered).unwrap();
+        assert_eq!(journal.len().unwrap(), 1);
+        assert_eq!(replayed(&journal), HashSet::from([later]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn disabled_journal_stays_empty() {
+        let (store, journal, dir) = journaled();
+        EngagementStore::insert(&store, engagement("Before")).unwrap();
+
+        journal.disable().unwrap();
+        EngagementStore::insert(&store, engagement("After")).unwrap();
+        assert_eq!(journal.len().unwrap(), 0);
+        assert!(journal.entries().unwrap().is_empty());
+        assert_eq!(EngagementStore::list(&store).unwrap().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reopening_drops_a_torn_final_line() {
+        let (store, journal, dir) = journaled();
+        let kept = engagement("Kept");
+        EngagementStore::insert(&store, kept.clone()).unwrap();
+        drop(store);
+        drop(journal);
+
+        let path = dir.join("journal.jsonl");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"timestamp\":\"2024-").unwrap();
+        drop(file);
+
+        let journal = Arc::new(Journal::open(&path).unwrap());
+        let store = JournaledStore::new(Arc::new(LocalStore::in_memory()), journal.clone());
+        let later = engagement("Later");
+        EngagementStore::insert(&store, later.clone()).unwrap();
+
+        assert_eq!(journal.len().unwrap(), 2);
+        assert_eq!(replayed(&journal), HashSet::from([kept, later]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_are_not_journaled() {
+        let dir = std::env::temp_dir().join(format!("journal-test-{}", Uuid::new_v4()));
+        let data_dir = dir.join("data");
+        let journal = Arc::new(Journal::open(dir.join("journal.jsonl")).unwrap());
+        let store = JournaledStore::new(
+            Arc::new(LocalStore::open(&data_dir).unwrap()),
+            journal.clone(),
+        );
+        fs::remove_dir_all(&data_dir).unwrap();
+
+        assert!(EngagementStore::insert(&store, engagement("Lost")).is_err());
+        assert_eq!(journal.len().unwrap(), 0);
+        assert!(replayed(&journal).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use chrono::{DateTime, Duration, Utc};
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::store::StoreError;
+
+// Failures allowed before backoff starts. IPs get more headroom since one
+// address can be shared by a whole office.
+const USER_FREE_ATTEMPTS: u32 = 3;
+const IP_FREE_ATTEMPTS: u32 = 10;
+// The first lockout lasts this long and doubles with every further failure
+const BASE_LOCKOUT_SECS: i64 = 5;
+const MAX_LOCKOUT_SECS: i64 = 15 * 60;
+// Counters are forgotten once this much time passes without a failure
+const RESET_AFTER_SECS: i64 = 60 * 60;
+
+/// What a failed-attempt counter is tracking.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(tag = "kind", content = "value")]
+pub enum ThrottleKey {
+    User(String),
+    Ip(String),
+}
+
+impl ThrottleKey {
+    fn free_attempts(&self) -> u32 {
+        match self {
+            ThrottleKey::User(_) => USER_FREE_ATTEMPTS,
+            ThrottleKey::Ip(_) => IP_FREE_ATTEMPTS,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Lockout {
+    pub key: ThrottleKey,
+    pub failures: u32,
+    pub last_failure: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug)]
+struct Attempts {
+    failures: u32,
+    last_failure: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+/// Tracks failed logins per username and per client IP. Once a key runs out
+/// of free attempts, each further failure locks it for an exponentially
+/// growing period, capped at `MAX_LOCKOUT_SECS`.
+#[derive(Default)]
+pub struct LoginThrottle {
+    attempts: Mutex<HashMap<ThrottleKey, Attempts>>,
+}
+
+impl LoginThrottle {
+    /// Returns when a login involving `keys` may be tried again, if any of
+    /// them is locked right now.
+    pub fn locked_until(&self, keys: &[ThrottleKey]) -> Result<Option<DateTime<Utc>>, StoreError> {
+        let now = Utc::now();
+        let attempts = self.attempts.lock().map_err(|_| StoreError::Lock)?;
+        Ok(keys
+            .iter()
+            .filter_map(|key| attempts.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .max())
+    }
+
+    pub fn record_failure(&self, keys: &[ThrottleKey]) -> Result<(), StoreError> {
+        let now = Utc::now();
+        let mut attempts = self.attempts.lock().map_err(|_| StoreError::Lock)?;
+        attempts.retain(|_, a| now - a.last_failure < Duration::seconds(RESET_AFTER_SECS));
+
+        for key in keys {
+            let entry = attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+            entry.failures += 1;
+            entry.last_failure = now;
+
+            if let Some(excess) = entry.failures.checked_sub(key.free_attempts() + 1) {
+                let secs = (BASE_LOCKOUT_SECS << excess.min(20)).min(MAX_LOCKOUT_SECS);
+                entry.locked_until = Some(now + Duration::seconds(secs));
+            }
+        }
+        Ok(())
+    }
+
+    /// Clears the counter for `key`, e.g. after a successful login.
+    pub fn clear(&self, key: &ThrottleKey) -> Result<bool, StoreError> {
+        let mut attempts = self.attempts.lock().map_err(|_| StoreError::Lock)?;
+        Ok(attempts.remove(key).is_some())
+    }
+
+    /// Lists every tracked key, most recent failure first.
+    pub fn list(&self) -> Result<Vec<Lockout>, StoreError> {
+        let now = Utc::now();
+        let attempts = self.attempts.lock().map_err(|_| StoreError::Lock)?;
+        let mut lockouts: Vec<Lockout> = attempts
+            .iter()
+            .filter(|(_, a)| now - a.last_failure < Duration::seconds(RESET_AFTER_SECS))
+            .map(|(key, a)| Lockout {
+                key: key.clone(),
+                failures: a.failures,
+                last_failure: a.last_failure,
+                locked_until: a.locked_until.filter(|until| *until > now),
+            })
+            .collect();
+        lockouts.sort_by_key(|l| std::cmp::Reverse(l.last_failure));
+        Ok(lockouts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> [ThrottleKey; 2] {
+        [
+            ThrottleKey::User("ann".to_string()),
+            ThrottleKey::Ip("10.0.0.1".to_string()),
+        ]
+    }
+
+    fn lockout_secs(throttle: &LoginThrottle, key: &ThrottleKey) -> Option<i64> {
+        let until = throttle.locked_until(std::slice::from_ref(key)).unwrap()?;
+        Some((until - Utc::now()).num_seconds() + 1)
+    }
+
+    #[test]
+    fn users_lock_after_their_free_attempts() {
+        let throttle = LoginThrottle::default();
+        let [user, ip] = keys();
+        for _ in 0..USER_FREE_ATTEMPTS {
+            throttle.record_failure(&keys()).unwrap();
+        }
+        assert_eq!(throttle.locked_until(&keys()).unwrap(), None);
+
+        throttle.record_failure(&keys()).unwrap();
+        assert_eq!(lockout_secs(&throttle, &user), Some(BASE_LOCKOUT_SECS));
+        // The address has more headroom than the account
+        assert_eq!(lockout_secs(&throttle, &ip), None);
+
+        throttle.record_failure(&keys()).unwrap();
+        assert_eq!(lockout_secs(&throttle, &user), Some(BASE_LOCKOUT_SECS * 2));
+    }
+
+    #[test]
+    fn lockouts_are_capped() {
+        let throttle = LoginThrottle::default();
+        let [user, _] = keys();
+        for _ in 0..100 {
+            throttle
+                .record_failure(std::slice::from_ref(&user))
+                .unwrap();
+        }
+        assert_eq!(lockout_secs(&throttle, &user), Some(MAX_LOCKOUT_SECS));
+    }
+
+    #[test]
+    fn counters_can_be_cleared_and_expire() {
+        let throttle = LoginThrottle::default();
+        let [user, ip] = keys();
+        for _ in 0..=USER_FREE_ATTEMPTS {
+            throttle.record_failure(&keys()).unwrap();
+        }
+        assert_eq!(throttle.list().unwrap().len(), 2);
+
+        assert!(throttle.clear(&user).unwrap());
+        assert!(!throttle.clear(&user).unwrap());
+        assert_eq!(throttle.locked_until(&keys()).unwrap(), None);
+
+        throttle
+            .attempts
+            .lock()
+            .unwrap()
+            .get_mut(&ip)
+            .unwrap()
+            .last_failure = Utc::now() - Duration::seconds(RESET_AFTER_SECS);
+        assert!(throttle.list().unwrap().is_empty());
+        throttle.record_failure(std::slice::from_ref(&ip)).unwrap();
+        assert_eq!(throttle.list().unwrap()[0].failures, 1);
+    }
+}
@@ -1,39 +1,66 @@
 use actix_identity::IdentityMiddleware;
 use actix_route_rate_limiter::{LimiterBuilder, RateLimiter};
-use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, middleware::Logger, web::{self, Data}, App, HttpServer};
+use actix_session::{config::BrowserSession, SessionMiddleware};
+use actix_web::{
+    cookie::time::Duration,
+    dev::ServiceRequest,
+    middleware::Logger,
+    web::{self, Data},
+    App, HttpServer,
+};
 use auth::UserStore;
 use dotenv::dotenv;
 use rustls::{Certificate, PrivateKey, ServerConfig};
 use rustls_pemfile::{certs, pkcs8_private_keys};
-use std::{
-    collections::{HashMap, HashSet},
-    env,
-    fs::File,
-    io::BufReader,
-    sync::{Arc, Mutex},
-};
+use std::{env, fs::File, io::BufReader, sync::Arc};
 
+mod account;
 mod api;
+mod api_tokens;
+mod audit;
 mod auth;
 mod auth_middleware;
 mod backup;
+mod backup_crypto;
+mod backup_target;
+mod backups;
+mod calendar;
+mod csrf;
+mod csv_io;
 mod hosts;
+mod ical;
 mod instructors;
+mod journal;
+mod login_throttle;
+mod public;
+mod revisions;
+mod roster;
 mod routing;
 mod security_headers;
+mod session_store;
+mod store;
+mod tokens;
+mod trash;
+mod two_factor;
 mod types;
+mod users;
 mod views;
 
-use api::Engagement;
+use audit::AuditLog;
 use backup::{BackupConfig, BackupSystem};
+use journal::{Journal, JournaledStore};
+use login_throttle::LoginThrottle;
 use security_headers::SecurityHeaders;
+use session_store::{SessionBackend, SessionSettings};
+use store::LocalStore;
 use types::*;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().expect("Failed to read .env file");
-    std::env::set_var("RUST_LOG", "debug");
+    if env::var_os("RUST_LOG").is_none() {
+        std::env::set_var("RUST_LOG", "info");
+    }
     env_logger::init();
 
     let listen_addr = env::var("LISTEN_ADDR").expect("LISTEN_HTTP must be set");
@@ -42,61 +69,180 @@ async fn main() -> std::io::Result<()> {
     let username = env::var("ADMIN_USERNAME").expect("ADMIN_USERNAME must be set");
     let password = env::var("ADMIN_PASSWORD").expect("ADMIN_PASSWORD must be set");
     let rustls_config = load_rustls_config(&cert_path, &key_path)?;
-    let secret_key = Key::generate();
 
-    let engagements: Arc<Mutex<HashSet<Engagement>>> = Arc::new(Mutex::new(HashSet::new()));
-    let instructors = InstructorRepo::new();
-    let hosts = HostRepo::new();
+    let data_dir = env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string());
+    let journal = Arc::new(
+        Journal::open(std::path::Path::new(&data_dir).join("journal.jsonl"))
+            .map_err(std::io::Error::other)?,
+    );
+    let store = Arc::new(JournaledStore::new(
+        Arc::new(open_store(&data_dir)?),
+        journal.clone(),
+    ));
+    let audit = Arc::new(
+        AuditLog::open(std::path::Path::new(&data_dir).join("audit.jsonl"))
+            .map_err(std::io::Error::other)?,
+    );
+    let engagements = EngagementRepo::new(store.clone());
+    let trash = TrashRepo::new(store.clone());
+    let instructors = InstructorRepo::new(store.clone());
+    let hosts = HostRepo::new(store);
 
     let backup_engagements = engagements.clone();
+    let backup_trash = trash.clone();
     let backup_instructors = instructors.clone();
     let backup_hosts = hosts.clone();
 
-    if let Err(e) =
-        configure_backup_system(backup_engagements.clone(), backup_instructors, backup_hosts).await
+    let backup_system = match configure_backup_system(
+        backup_engagements,
+        backup_trash,
+        backup_instructors,
+        backup_hosts,
+        journal.clone(),
+        audit.clone(),
+    )
+    .await
     {
-        log::error!("Failed to configure backup system: {}", e);
-    }
+        Ok(backup_system) => Some(Data::from(backup_system)),
+        Err(e) => {
+            log::error!("Failed to configure backup system: {}", e);
+            // Only backups truncate the journal, so it would grow forever
+            log::warn!("Disabling the change journal as there are no backups to replay it onto");
+            journal.disable().map_err(std::io::Error::other)?;
+            None
+        }
+    };
 
     let limiter = LimiterBuilder::new()
         .with_duration(chrono::Duration::minutes(1))
         .with_num_requests(60)
         .build();
 
-    let users = Data::new(UserStore(Mutex::new(HashMap::new())));
-    auth::create_user(
-        &users,
-        &username,
-        &password,
-    )
-    .expect("Failed to create admin user");
+    // Published schedules get their own allowance, so attendee traffic can't
+    // use up the one admins rely on
+    let public_feed = Data::new(public::PublicFeedSettings::from_env()?);
+    let public_limiter = LimiterBuilder::new()
+        .with_duration(chrono::Duration::minutes(1))
+        .with_num_requests(public_feed.requests_per_minute)
+        .build();
+    let public_feed_state = Data::new(public::PublicFeedState::default());
+
+    let users = Data::new(
+        UserStore::open(std::path::Path::new(&data_dir).join("users.json"))
+            .map_err(std::io::Error::other)?,
+    );
+    // The admin account only seeds an empty store; afterwards users are
+    // managed through the app
+    if users.is_empty().map_err(std::io::Error::other)? {
+        auth::create_user(&users, &username, &password, auth::Role::Admin, Vec::new())
+            .expect("Failed to create admin user");
+    }
+    let api_tokens = Data::new(
+        api_tokens::ApiTokenStore::open(std::path::Path::new(&data_dir).join("api_tokens.json"))
+            .map_err(std::io::Error::other)?,
+    );
+    let session = SessionSettings::from_env(std::path::Path::new(&data_dir))?;
+    let session_store = match &session.backend {
+        SessionBackend::File(store) => Some(Data::new(store.clone())),
+        SessionBackend::Cookie => None,
+    };
+    let revisions = Data::new(
+        revisions::RevisionStore::open(std::path::Path::new(&data_dir).join("revisions.json"))
+            .map_err(std::io::Error::other)?,
+    );
+    let trash_settings = Data::new(trash::TrashSettings::from_env()?);
+    trash::start_purge_task(
+        trash.clone(),
+        revisions.clone(),
+        audit.clone(),
+        trash_settings.clone(),
+    );
+    let login_throttle = Data::new(LoginThrottle::default());
+    let auth_settings = Data::new(auth::AuthSettings::from_env());
 
     HttpServer::new(move || {
         App::new()
-            .wrap(IdentityMiddleware::default())
-            .wrap(SessionMiddleware::new(
-                CookieSessionStore::default(),
-                secret_key.clone(),
-            ))
-            .wrap(Logger::default())
+            .wrap(csrf::CsrfProtection)
+            .wrap(
+                IdentityMiddleware::builder()
+                    .visit_deadline(session.idle_timeout)
+                    .login_deadline(session.absolute_timeout)
+                    .build(),
+            )
+            .wrap({
+                let builder = SessionMiddleware::builder(session.backend.clone(), session.key.clone());
+                match session.absolute_timeout {
+                    // Server-side state shouldn't outlive the login it belongs to
+                    Some(timeout) => builder
+                        .session_lifecycle(
+                            BrowserSession::default()
+                                .state_ttl(Duration::seconds(timeout.as_secs() as i64)),
+                        )
+                        .build(),
+                    None => builder.build(),
+                }
+            })
+            // As `Logger::default()`, but without secrets from the query string
+            .wrap(
+                Logger::new(ACCESS_LOG_FORMAT)
+                    .custom_request_replace("request_line", redacted_request_line),
+            )
             .wrap(SecurityHeaders)
-            .wrap(RateLimiter::new(Arc::clone(&limiter)))
             .app_data(Data::new(engagements.clone()))
             .app_data(Data::new(instructors.clone()))
             .app_data(Data::new(hosts.clone()))
+            .app_data(Data::new(roster::Roster::new(instructors.clone(), hosts.clone())))
+            .app_data(Data::new(trash.clone()))
+            .app_data(Data::from(audit.clone()))
             .app_data(users.clone())
-            // Public routes (login)
-            .service(auth::login_page)
-            .service(auth::login)
-            .service(auth::logout)
-            // Protected routes
+            .app_data(api_tokens.clone())
+            .app_data(login_throttle.clone())
+            .app_data(auth_settings.clone())
+            .app_data(public_feed.clone())
+            .app_data(public_feed_state.clone())
+            .app_data(trash_settings.clone())
+            .app_data(revisions.clone())
+            .configure(|cfg| {
+                if let Some(backup_system) = &backup_system {
+                    cfg.app_data(backup_system.clone());
+                }
+                if let Some(session_store) = &session_store {
+                    cfg.app_data(session_store.clone());
+                }
+            })
+            // Published schedules, open to everyone
+            .service(
+                web::scope("/public")
+                    .wrap(RateLimiter::new(Arc::clone(&public_limiter)))
+                    .configure(routing::config_public_paths),
+            )
             .service(
                 web::scope("")
-                    .wrap(auth_middleware::AuthMiddleware)
-                    .configure(routing::config_eng_paths)
-                    .configure(routing::config_view_paths)
-                    .configure(routing::config_ins_paths)
-                    .configure(routing::config_hosts_paths)
+                    .wrap(RateLimiter::new(Arc::clone(&limiter)))
+                    // Public routes (login)
+                    .service(auth::login_page)
+                    .service(auth::login)
+                    .service(auth::two_factor_page)
+                    .service(auth::two_factor_login)
+                    .service(auth::logout)
+                    // Calendar subscriptions, authenticated by the token in their URL
+                    .configure(routing::config_calendar_paths)
+                    // Protected routes
+                    .service(
+                        web::scope("")
+                            .wrap(auth_middleware::AuthMiddleware)
+                            .configure(routing::config_eng_paths)
+                            .configure(routing::config_trash_paths)
+                            .configure(routing::config_view_paths)
+                            .configure(routing::config_ins_paths)
+                            .configure(routing::config_hosts_paths)
+                            .configure(routing::config_backup_paths)
+                            .configure(routing::config_csv_paths)
+                            .configure(routing::config_user_paths)
+                            .configure(routing::config_token_paths)
+                            .configure(routing::config_audit_paths)
+                            .configure(routing::config_account_paths)
+                    ),
             )
     })
     .bind_rustls(&listen_addr, rustls_config)?
@@ -104,6 +250,43 @@ async fn main() -> std::io::Result<()> {
     .await
 }
 
+const ACCESS_LOG_FORMAT: &str =
+    r#"%a "%{request_line}xi" %s %b "%{Referer}i" "%{User-Agent}i" %T"#;
+
+/// The request line for the access log, with the value of any `token` query
+/// parameter hidden. Calendar subscription URLs carry an API token there.
+fn redacted_request_line(req: &ServiceRequest) -> String {
+    let query: Vec<String> = req
+        .query_string()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some(("token", _)) => "token=[REDACTED]".to_string(),
+            _ => pair.to_string(),
+        })
+        .collect();
+
+    let target = if query.is_empty() {
+        req.path().to_string()
+    } else {
+        format!("{}?{}", req.path(), query.join("&"))
+    };
+    format!("{} {} {:?}", req.method(), target, req.version())
+}
+
+fn open_store(data_dir: &str) -> std::io::Result<LocalStore> {
+    match env::var("STORAGE_BACKEND").as_deref() {
+        Ok("memory") => {
+            log::warn!("Using in-memory storage; changes will be lost on restart");
+            Ok(LocalStore::in_memory())
+        }
+        _ => {
+            log::info!("Using local storage in {}", data_dir);
+            LocalStore::open(data_dir).map_err(std::io::Error::other)
+        }
+    }
+}
+
 fn load_rustls_config(cert_path: &str, key_path: &str) -> std::io::Result<ServerConfig> {
     let cert_file = &mut BufReader::new(File::open(cert_path)?);
     let cert_chain = certs(cert_file)
@@ -136,50 +319,95 @@ fn load_rustls_config(cert_path: &str, key_path: &str) -> std::io::Result<Server
 }
 
 async fn configure_backup_system(
-    engagements: Arc<Mutex<HashSet<Engagement>>>,
+    engagements: EngagementRepo,
+    trash: TrashRepo,
     instructors: InstructorRepo,
     hosts: HostRepo,
-) -> Result<(), Box<dyn std::error::Error>> {
+    journal: Arc<Journal>,
+    audit: Arc<AuditLog>,
+) -> Result<Arc<BackupSystem>, Box<dyn std::error::Error>> {
     let config = BackupConfig::from_env()?;
-    let backup_system = BackupSystem::new(
-        engagements.clone(),
-        instructors.0.clone(),
-        hosts.0.clone(),
-        config,
-    )
-    .await?;
+    let backup_system = Arc::new(
+        BackupSystem::new(
+            engagements.clone(),
+            trash.clone(),
+            instructors.clone(),
+            hosts.clone(),
+            journal.clone(),
+            audit.clone(),
+            config,
+        )
+        .await?,
+    );
 
-    {
-        let (mut engagements_guard, mut instructors_guard, mut hosts_guard) = (
-            engagements.lock().unwrap(),
-            instructors.lock().unwrap(),
-            hosts.lock().unwrap(),
-        );
+    let (engagements_empty, instructors_empty, hosts_empty, audit_empty) = (
+        engagements.list()?.is_empty(),
+        instructors.list()?.is_empty(),
+        hosts.list()?.is_empty(),
+        audit.entries()?.is_empty(),
+    );
 
-        if engagements_guard.is_empty() || instructors_guard.is_empty() || hosts_guard.is_empty() {
-            match backup_system.restore_latest_backup().await {
-                Ok((restored_engagements, restored_instructors, restored_hosts)) => {
-                    if engagements_guard.is_empty() {
-                        *engagements_guard = restored_engagements;
-                        log::info!("Successfully restored engagements from latest backup");
-                    }
-                    if instructors_guard.is_empty() {
-                        *instructors_guard = restored_instructors;
-                        log::info!("Successfully restored instructors from latest backup");
-                    }
-                    if hosts_guard.is_empty() {
-                        *hosts_guard = restored_hosts;
-                        log::info!("Successfully restored hosts from latest backup");
+    if engagements_empty || instructors_empty || hosts_empty || audit_empty {
+        match backup_system.restore_latest_backup().await {
+            Ok(mut restored) => {
+                let replayed = journal.replay(
+                    &mut restored.engagements,
+                    &mut restored.trash,
+                    &mut restored.instructors,
+                    &mut restored.hosts,
+                )?;
+                log::info!("Replayed {} journaled changes on top of latest backup", replayed);
+
+                if engagements_empty {
+                    engagements.replace_all(restored.engagements)?;
+                    log::info!("Successfully restored engagements from latest backup");
+                    // The trash was lost along with the engagements
+                    if trash.list()?.is_empty() {
+                        trash.replace_all(restored.trash)?;
+                        log::info!("Successfully restored trash from latest backup");
                     }
                 }
-                Err(e) => {
-                    log::error!("Failed to restore data from backup: {}", e);
+                if instructors_empty {
+                    instructors.replace_all(restored.instructors)?;
+                    log::info!("Successfully restored instructors from latest backup");
+                }
+                if hosts_empty {
+                    hosts.replace_all(restored.hosts)?;
+                    log::info!("Successfully restored hosts from latest backup");
                 }
+                if audit_empty {
+                    audit.merge(restored.audit)?;
+                    log::info!("Successfully restored audit log from latest backup");
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to restore data from backup: {}", e);
             }
         }
     }
 
-    backup_system.start_backup_task().await;
+    backup_system.clone().start_backup_task().await;
 
-    Ok(())
+    Ok(backup_system)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn request_lines_hide_tokens() {
+        let req = TestRequest::get()
+            .uri("/calendar/engagements.ics?language=English&token=apt_secret")
+            .to_srv_request();
+        let line = redacted_request_line(&req);
+        assert_eq!(
+            line,
+            "GET /calendar/engagements.ics?language=English&token=[REDACTED] HTTP/1.1"
+        );
+
+        let req = TestRequest::get().uri("/views/index").to_srv_request();
+        assert_eq!(redacted_request_line(&req), "GET /views/index HTTP/1.1");
+    }
 }
@@ -0,0 +1,312 @@
+use actix_web::{
+    get,
+    http::header::{
+        CacheControl, CacheDirective, ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch,
+        LastModified,
+    },
+    web::{Data, Path},
+    HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use askama_actix::Template;
+use chrono::{DateTime, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, env, io, sync::Mutex, time::SystemTime};
+
+use crate::api::{compare_engagement_dates, Engagement, Language, Status};
+use crate::roster::{Roster, RosterNames};
+use crate::store::StoreError;
+use crate::types::EngagementRepo;
+
+// How long browsers and proxies may reuse a feed before checking it again
+const MAX_AGE_SECS: u32 = 5 * 60;
+
+/// Which languages are published without a login, read from the
+/// environment at startup. Nothing is published unless it's listed.
+pub struct PublicFeedSettings {
+    pub languages: Vec<Language>,
+    /// Requests per minute each client may make to the public routes
+    pub requests_per_minute: usize,
+}
+
+impl PublicFeedSettings {
+    pub fn from_env() -> io::Result<Self> {
+        let languages = match env::var("PUBLIC_FEED_LANGUAGES") {
+            Ok(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    serde_json::from_value(serde_json::Value::String(name.to_string())).map_err(
+                        |_| {
+                            io::Error::other(format!(
+                                "PUBLIC_FEED_LANGUAGES: unknown language '{}'",
+                                name
+                            ))
+                        },
+                    )
+                })
+                .collect::<io::Result<Vec<Language>>>()?,
+            Err(_) => Vec::new(),
+        };
+
+        let requests_per_minute = match env::var("PUBLIC_FEED_REQUESTS_PER_MINUTE") {
+            Ok(value) => value.parse().map_err(|e| {
+                io::Error::other(format!(
+                    "PUBLIC_FEED_REQUESTS_PER_MINUTE must be a whole number: {}",
+                    e
+                ))
+            })?,
+            Err(_) => 30,
+        };
+
+        Ok(Self {
+            languages,
+            requests_per_minute,
+        })
+    }
+}
+
+/// A confirmed engagement as published to attendees. Ids and statuses are
+/// internal and left out.
+#[derive(serde::Serialize)]
+pub struct PublicEngagement {
+    pub title: String,
+    pub date: String,
+    pub part: usize,
+    pub num_parts: usize,
+    pub instructor: String,
+    pub host: String,
+    pub language: Language,
+}
+
+impl PublicEngagement {
+    fn new(engagement: Engagement, names: &RosterNames) -> Self {
+        Self {
+            title: engagement.title,
+            date: engagement.date,
+            part: engagement.part,
+            num_parts: engagement.num_parts,
+            instructor: names.instructor(&engagement.instructor_id).to_string(),
+            host: names.host(&engagement.host_id).to_string(),
+            language: engagement.language,
+        }
+    }
+}
+
+/// Remembers when each language's feed last changed. Engagements don't
+/// record their own modification times, so a feed counts as modified when
+/// its content hash differs from the one served before.
+#[derive(Default)]
+pub struct PublicFeedState {
+    versions: Mutex<HashMap<String, (String, DateTime<Utc>)>>,
+}
+
+impl PublicFeedState {
+    fn last_modified(&self, language: &Language, hash: &str) -> Result<DateTime<Utc>, StoreError> {
+        let mut versions = self.versions.lock().map_err(|_| StoreError::Lock)?;
+        let entry = versions
+            .entry(language.to_string())
+            .or_insert_with(|| (hash.to_string(), now_to_the_second()));
+        if entry.0 != hash {
+            *entry = (hash.to_string(), now_to_the_second());
+        }
+        Ok(entry.1)
+    }
+}
+
+// HTTP dates only have whole seconds, so anything finer would never compare
+// equal to an If-Modified-Since header
+fn now_to_the_second() -> DateTime<Utc> {
+    Utc.timestamp_opt(Utc::now().timestamp(), 0)
+        .single()
+        .unwrap_or_else(Utc::now)
+}
+
+struct Feed {
+    engagements: Vec<PublicEngagement>,
+    hash: String,
+    last_modified: SystemTime,
+}
+
+impl Feed {
+    /// The JSON and HTML renderings of a feed are different representations,
+    /// so each gets its own tag.
+    fn etag(&self, representation: &str) -> EntityTag {
+        EntityTag::new_strong(format!("{}-{}", representation, &self.hash[..32]))
+    }
+}
+
+/// Builds the published feed for `language`, or `None` if that language
+/// isn't published.
+fn load_feed(
+    repo: &EngagementRepo,
+    roster: &Roster,
+    settings: &PublicFeedSettings,
+    state: &PublicFeedState,
+    language: &Language,
+) -> Result<Option<Feed>, actix_web::Error> {
+    if !settings.languages.contains(language) {
+        return Ok(None);
+    }
+
+    let mut engagements: Vec<Engagement> = repo
+        .list()?
+        .into_iter()
+        .filter(|e| e.language == *language && e.status == Status::Confirmed)
+        .collect();
+    engagements
+        .sort_by(|a, b| compare_engagement_dates(a, b).unwrap_or_else(|_| a.date.cmp(&b.date)));
+    // Names are resolved here so a renamed instructor or host changes the
+    // feed's hash like any other edit
+    let names = roster.names()?;
+    let engagements: Vec<PublicEngagement> = engagements
+        .into_iter()
+        .map(|e| PublicEngagement::new(e, &names))
+        .collect();
+
+    let body = serde_json::to_vec(&engagements)?;
+    let hash = format!("{:x}", Sha256::digest(&body));
+    let last_modified = state.last_modified(language, &hash)?;
+
+    Ok(Some(Feed {
+        engagements,
+        hash,
+        last_modified: last_modified.into(),
+    }))
+}
+
+/// Whether the client's cached copy, as described by its conditional
+/// headers, is still current. If-None-Match wins when both are sent.
+fn is_fresh(req: &HttpRequest, etag: &EntityTag, last_modified: SystemTime) -> bool {
+    if let Some(if_none_match) = req.get_header::<IfNoneMatch>() {
+        return match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        };
+    }
+
+    req.get_header::<IfModifiedSince>()
+        .is_some_and(|since| SystemTime::from(since.0) >= last_modified)
+}
+
+fn with_cache_headers(
+    mut builder: HttpResponseBuilder,
+    etag: EntityTag,
+    last_modified: SystemTime,
+) -> HttpResponseBuilder {
+    builder
+        .insert_header(ETag(etag))
+        .insert_header(LastModified(HttpDate::from(last_modified)))
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(MAX_AGE_SECS),
+        ]));
+    builder
+}
+
+#[get("/engs/{lang}")]
+pub async fn public_engagements(
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    settings: Data<PublicFeedSettings>,
+    state: Data<PublicFeedState>,
+    lang: Path<Language>,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(feed) = load_feed(&repo, &roster, &settings, &state, &lang)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let etag = feed.etag("json");
+    let last_modified = feed.last_modified;
+    if is_fresh(&req, &etag, last_modified) {
+        return Ok(with_cache_headers(HttpResponse::NotModified(), etag, last_modified).finish());
+    }
+
+    Ok(with_cache_headers(HttpResponse::Ok(), etag, last_modified)
+        .content_type("application/json; charset=utf-8")
+        .json(&feed.engagements))
+}
+
+#[derive(Template)]
+#[template(path = "public.html")]
+struct PublicScheduleTemplate {
+    lang: String,
+    engagements: Vec<PublicEngagement>,
+}
+
+#[get("/{lang}")]
+pub async fn public_schedule(
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    settings: Data<PublicFeedSettings>,
+    state: Data<PublicFeedState>,
+    lang: Path<Language>,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(feed) = load_feed(&repo, &roster, &settings, &state, &lang)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let etag = feed.etag("html");
+    let last_modified = feed.last_modified;
+    if is_fresh(&req, &etag, last_modified) {
+        return Ok(with_cache_headers(HttpResponse::NotModified(), etag, last_modified).finish());
+    }
+
+    let template = PublicScheduleTemplate {
+        lang: lang.to_string(),
+        engagements: feed.engagements,
+    };
+    let body = template
+        .render()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(with_cache_headers(HttpResponse::Ok(), etag, last_modified)
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Language;
+    use actix_web::test::TestRequest;
+    use std::time::Duration;
+
+    #[test]
+    fn feeds_keep_their_time_until_the_content_changes() {
+        let state = PublicFeedState::default();
+        let first = state.last_modified(&Language::English, "a").unwrap();
+        assert_eq!(state.last_modified(&Language::English, "a").unwrap(), first);
+        assert_eq!(first.timestamp_subsec_nanos(), 0);
+        assert!(state.last_modified(&Language::English, "b").unwrap() >= first);
+    }
+
+    #[test]
+    fn conditional_headers_decide_freshness() {
+        let etag = EntityTag::new_strong("json-abc".to_string());
+        let other = EntityTag::new_strong("json-def".to_string());
+        let last_modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let earlier = last_modified - Duration::from_secs(60);
+        let fresh = |req: TestRequest| is_fresh(&req.to_http_request(), &etag, last_modified);
+
+        assert!(!fresh(TestRequest::default()));
+        assert!(fresh(
+            TestRequest::default().insert_header(IfNoneMatch::Items(vec![etag.clone()]))
+        ));
+        assert!(fresh(TestRequest::default().insert_header(IfNoneMatch::Any)));
+        assert!(fresh(
+            TestRequest::default().insert_header(IfModifiedSince(last_modified.into()))
+        ));
+        assert!(!fresh(
+            TestRequest::default().insert_header(IfModifiedSince(earlier.into()))
+        ));
+        // A mismatched tag wins over an up to date If-Modified-Since
+        assert!(!fresh(
+            TestRequest::default()
+                .insert_header(IfNoneMatch::Items(vec![other]))
+                .insert_header(IfModifiedSince(last_modified.into()))
+        ));
+    }
+}
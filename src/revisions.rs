@@ -0,0 +1,394 @@
+use actix_web::{
+    get, post,
+    web::{Data, Path},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::{collections::HashMap, fs::File, io::BufReader, path::PathBuf, sync::Mutex};
+use uuid::Uuid;
+
+use crate::api::{find_eng, version_conflict, Engagement};
+use crate::audit::{AuditAction, AuditLog, AuditRecord};
+use crate::auth::{CurrentUser, Role};
+use crate::roster::{Roster, RosterNames};
+use crate::store::{persist, StoreError, VersionedUpdate};
+use crate::types::EngagementRepo;
+
+/// One saved state of an engagement.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Revision {
+    /// Position in the engagement's history, starting at 1
+    pub number: usize,
+    pub engagement: Engagement,
+    /// Who saved this state and when. Both are unknown for the baseline
+    /// kept when an engagement from before revision history is first edited.
+    pub edited_by: Option<String>,
+    pub edited_at: Option<DateTime<Utc>>,
+    /// Set when this revision brought back an earlier one
+    pub reverted_from: Option<usize>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+/// The fields that differ between two states of an engagement, with
+/// instructors and hosts shown by name.
+pub fn changes(before: &Engagement, after: &Engagement, names: &RosterNames) -> Vec<FieldChange> {
+    let fields = [
+        ("title", before.title.clone(), after.title.clone()),
+        ("date", before.date.clone(), after.date.clone()),
+        (
+            "status",
+            before.status.to_string(),
+            after.status.to_string(),
+        ),
+        (
+            "instructor",
+            names.instructor(&before.instructor_id).to_string(),
+            names.instructor(&after.instructor_id).to_string(),
+        ),
+        (
+            "host",
+            names.host(&before.host_id).to_string(),
+            names.host(&after.host_id).to_string(),
+        ),
+        ("part", before.part.to_string(), after.part.to_string()),
+        (
+            "num_parts",
+            before.num_parts.to_string(),
+            after.num_parts.to_string(),
+        ),
+        (
+            "language",
+            before.language.to_string(),
+            after.language.to_string(),
+        ),
+    ];
+
+    fields
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(field, before, after)| FieldChange {
+            field,
+            before,
+            after,
+        })
+        .collect()
+}
+
+/// Revision histories keyed by engagement id, persisted to a JSON file on
+/// every change.
+pub struct RevisionStore {
+    revisions: Mutex<HashMap<Uuid, Vec<Revision>>>,
+    path: PathBuf,
+}
+
+impl RevisionStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let path = path.into();
+        let revisions = if path.exists() {
+            serde_json::from_reader(BufReader::new(File::open(&path)?))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            revisions: Mutex::new(revisions),
+            path,
+        })
+    }
+
+    /// Every revision of an engagement, oldest first.
+    pub fn history(&self, id: Uuid) -> Result<Vec<Revision>, StoreError> {
+        let revisions = self.revisions.lock().map_err(|_| StoreError::Lock)?;
+        Ok(revisions.get(&id).cloned().unwrap_or_default())
+    }
+
+    /// Records a newly added engagement as its first revision.
+    pub fn record_created(&self, engagement: &Engagement, user: &str) -> Result<(), StoreError> {
+        self.modify(|revisions| {
+            revisions.insert(
+                engagement.id,
+                vec![Revision {
+                    number: 1,
+                    engagement: engagement.clone(),
+                    edited_by: Some(user.to_string()),
+                    edited_at: Some(Utc::now()),
+                    reverted_from: None,
+                }],
+            );
+        })
+    }
+
+    /// Records an edit from `before` to `after`, returning the new revision.
+    pub fn record_edit(
+        &self,
+        before: &Engagement,
+        after: &Engagement,
+        user: &str,
+        reverted_from: Option<usize>,
+    ) -> Result<Revision, StoreError> {
+        self.modify(|revisions| {
+            let history = revisions.entry(after.id).or_default();
+            // Engagements from before revision history start with their
+            // state at the first edit
+            if history.is_empty() {
+                history.push(Revision {
+                    number: 1,
+                    engagement: before.clone(),
+                    edited_by: None,
+                    edited_at: None,
+                    reverted_from: None,
+                });
+            }
+
+            let revision = Revision {
+                number: history.len() + 1,
+                engagement: after.clone(),
+                edited_by: Some(user.to_string()),
+                edited_at: Some(Utc::now()),
+                reverted_from,
+            };
+            history.push(revision.clone());
+            revision
+        })
+    }
+
+    /// Drops the history of an engagement that has been purged.
+    pub fn forget(&self, id: Uuid) -> Result<(), StoreError> {
+        self.modify(|revisions| {
+            revisions.remove(&id);
+        })
+    }
+
+    fn modify<R>(
+        &self,
+        f: impl FnOnce(&mut HashMap<Uuid, Vec<Revision>>) -> R,
+    ) -> Result<R, StoreError> {
+        let mut revisions = self.revisions.lock().map_err(|_| StoreError::Lock)?;
+        let mut next = revisions.clone();
+        let result = f(&mut next);
+
+        persist(&self.path, &next)?;
+        *revisions = next;
+        Ok(result)
+    }
+}
+
+/// A revision as listed in an engagement's history, with what it changed
+/// compared to the revision before it.
+#[derive(serde::Serialize)]
+pub struct RevisionEntry {
+    #[serde(flatten)]
+    pub revision: Revision,
+    pub changes: Vec<FieldChange>,
+}
+
+#[get("/engs/{id}/revisions")]
+pub async fn get_revisions(
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    revisions: Data<RevisionStore>,
+    id: Path<Uuid>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Viewer)?;
+
+    let Some(current) = find_eng(&repo, *id)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    user.require_language(&current.language)?;
+
+    let names = roster.names()?;
+    let history = revisions.history(current.id)?;
+    let mut entries: Vec<RevisionEntry> = history
+        .iter()
+        .enumerate()
+        .map(|(i, revision)| RevisionEntry {
+            changes: match i {
+                0 => Vec::new(),
+                _ => changes(&history[i - 1].engagement, &revision.engagement, &names),
+            },
+            revision: revision.clone(),
+        })
+        .collect();
+    entries.reverse();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(entries))
+}
+
+/// Puts an engagement back the way it was at an earlier revision. The
+/// revert is itself recorded as a new revision, so it can be undone too.
+#[post("/engs/{id}/revisions/{number}/revert")]
+pub async fn revert_revision(
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
+    revisions: Data<RevisionStore>,
+    audit: Data<AuditLog>,
+    path: Path<(Uuid, usize)>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+    let (id, number) = path.into_inner();
+
+    let Some(current) = find_eng(&repo, id)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let Some(revision) = revisions
+        .history(id)?
+        .into_iter()
+        .find(|r| r.number == number)
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    user.require_language(&current.language)?;
+    user.require_language(&revision.engagement.language)?;
+
+    if changes(&current, &revision.engagement, &roster.names()?).is_empty() {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Nothing to revert",
+                "details": format!("The engagement already matches revision {}", number)
+            })));
+    }
+
+    // Whoever the revision had may have left the rosters or been made inactive since
+    if let Err(roster_error) = roster.check(&revision.engagement, Some(&current))? {
+        return Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Can't revert",
+                "details": roster_error
+            })));
+    }
+
+    let target_eng = match repo.update(revision.engagement, current.version)? {
+        VersionedUpdate::Updated(updated) => updated,
+        VersionedUpdate::Conflict(stored) => return Ok(version_conflict(&stored)),
+        VersionedUpdate::NotFound => return Ok(HttpResponse::NotFound().finish()),
+    };
+    revisions.record_edit(&current, &target_eng, &user.username, Some(number))?;
+    audit.record(
+        AuditRecord::new(&user.username, AuditAction::RevertEngagement, target_eng.id)
+            .before(&current)
+            .after(&target_eng),
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(target_eng))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Language, NewEngagement, Status};
+
+    fn engagement() -> Engagement {
+        NewEngagement {
+            instructor_id: Uuid::new_v4(),
+            host_id: Uuid::new_v4(),
+            date: "2024-05-01".to_string(),
+            language: Language::English,
+            title: "Intro".to_string(),
+            part: 1,
+            num_parts: 1,
+            status: Status::Planning,
+        }
+        .into_engagement()
+    }
+
+    fn store() -> (RevisionStore, PathBuf) {
+        let path = std::env::temp_dir().join(format!("revisions-test-{}.json", Uuid::new_v4()));
+        (RevisionStore::open(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn changes_list_only_differing_fields() {
+        let before = engagement();
+        let after = Engagement {
+            title: "Advanced".to_string(),
+            status: Status::Confirmed,
+            ..before.clone()
+        };
+
+        assert_eq!(
+            changes(&before, &after, &RosterNames::default()),
+            vec![
+                FieldChange {
+                    field: "title",
+                    before: "Intro".to_string(),
+                    after: "Advanced".to_string(),
+                },
+                FieldChange {
+                    field: "status",
+                    before: Status::Planning.to_string(),
+                    after: Status::Confirmed.to_string(),
+                },
+            ]
+        );
+        assert!(changes(&before, &before, &RosterNames::default()).is_empty());
+    }
+
+    #[test]
+    fn edits_are_numbered_and_kept() {
+        let (revisions, path) = store();
+        let created = engagement();
+        revisions.record_created(&created, "ann").unwrap();
+        let edited = Engagement {
+            title: "Edited".to_string(),
+            ..created.clone()
+        };
+        let revision = revisions
+            .record_edit(&created, &edited, "bob", None)
+            .unwrap();
+        assert_eq!(revision.number, 2);
+
+        let reverted = revisions
+            .record_edit(&edited, &created, "ann", Some(1))
+            .unwrap();
+        assert_eq!(reverted.number, 3);
+        assert_eq!(reverted.reverted_from, Some(1));
+
+        let history = RevisionStore::open(&path)
+            .unwrap()
+            .history(created.id)
+            .unwrap();
+        let editors: Vec<_> = history.iter().map(|r| r.edited_by.as_deref()).collect();
+        assert_eq!(editors, vec![Some("ann"), Some("bob"), Some("ann")]);
+
+        revisions.forget(created.id).unwrap();
+        assert!(revisions.history(created.id).unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn untracked_engagements_get_a_baseline_on_first_edit() {
+        let (revisions, path) = store();
+        let before = engagement();
+        let after = Engagement {
+            date: "2024-06-01".to_string(),
+            ..before.clone()
+        };
+
+        assert_eq!(
+            revisions
+                .record_edit(&before, &after, "ann", None)
+                .unwrap()
+                .number,
+            2
+        );
+        let history = revisions.history(before.id).unwrap();
+        assert_eq!(history[0].engagement.date, "2024-05-01");
+        assert_eq!(history[0].edited_by, None);
+        std::fs::remove_file(path).unwrap();
+    }
+}
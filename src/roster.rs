@@ -0,0 +1,273 @@
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::api::Engagement;
+use crate::store::StoreError;
+use crate::types::{HostRepo, InstructorRepo};
+
+/// Both rosters, for code that needs to resolve the instructor and host an
+/// engagement refers to.
+#[derive(Clone)]
+pub struct Roster {
+    pub instructors: InstructorRepo,
+    pub hosts: HostRepo,
+}
+
+impl Roster {
+    pub fn new(instructors: InstructorRepo, hosts: HostRepo) -> Self {
+        Self { instructors, hosts }
+    }
+
+    pub fn names(&self) -> Result<RosterNames, StoreError> {
+        Ok(RosterNames {
+            instructors: self
+                .instructors
+                .list()?
+                .into_iter()
+                .map(|i| (i.id, i.name))
+                .collect(),
+            hosts: self
+                .hosts
+                .list()?
+                .into_iter()
+                .map(|h| (h.id, h.name))
+                .collect(),
+        })
+    }
+
+    /// Checks that an engagement's instructor and host are on the rosters.
+    /// Inactive ones are only accepted if `current`, the engagement as it is
+    /// stored, already has them.
+    pub fn check(
+        &self,
+        engagement: &Engagement,
+        current: Option<&Engagement>,
+    ) -> Result<Result<(), String>, StoreError> {
+        let instructors = self.instructors.list()?;
+        match instructors.iter().find(|i| i.id == engagement.instructor_id) {
+            None => {
+                return Ok(Err(format!(
+                    "Unknown instructor: {}",
+                    engagement.instructor_id
+                )))
+            }
+            Some(i) if !i.active && current.is_none_or(|c| c.instructor_id != i.id) => {
+                return Ok(Err(format!(
+                    "{} is inactive and can't be given new engagements",
+                    i.name
+                )))
+            }
+            Some(_) => {}
+        }
+
+        let hosts = self.hosts.list()?;
+        match hosts.iter().find(|h| h.id == engagement.host_id) {
+            None => Ok(Err(format!("Unknown host: {}", engagement.host_id))),
+            Some(h) if !h.active && current.is_none_or(|c| c.host_id != h.id) => Ok(Err(format!(
+                "{} is inactive and can't be given new engagements",
+                h.name
+            ))),
+            Some(_) => Ok(Ok(())),
+        }
+    }
+}
+
+/// Display names of everyone on the rosters, by id.
+#[derive(Default)]
+pub struct RosterNames {
+    instructors: HashMap<Uuid, String>,
+    hosts: HashMap<Uuid, String>,
+}
+
+impl RosterNames {
+    pub fn instructor(&self, id: &Uuid) -> &str {
+        self.instructors
+            .get(id)
+            .map(String::as_str)
+            .unwrap_or("Unknown instructor")
+    }
+
+    pub fn host(&self, id: &Uuid) -> &str {
+        self.hosts
+            .get(id)
+            .map(String::as_str)
+            .unwrap_or("Unknown host")
+    }
+}
+
+/// Id for an instructor or host that was only a name before the rosters
+/// held records. It is derived from the name, so every old backup upgrades
+/// to the same ids independently.
+pub fn legacy_id(kind: &str, name: &str) -> Uuid {
+    let digest = Sha256::digest(format!("{}:{}", kind, name).as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Builder::from_custom_bytes(bytes).into_uuid()
+}
+
+fn legacy_record(kind: &str, name: &str, active: bool) -> Value {
+    json!({
+        "id": legacy_id(kind, name),
+        "name": name,
+        "email": "",
+        "phone": "",
+        "languages": [],
+        "time_zone": "",
+        "notes": "",
+        "active": active
+    })
+}
+
+/// Names that legacy engagements referred to, by roster.
+#[derive(Default)]
+struct LegacyNames {
+    instructors: BTreeSet<String>,
+    hosts: BTreeSet<String>,
+}
+
+/// Swaps the instructor and host names on an engagement for ids.
+fn upgrade_engagement(engagement: &mut Value, names: &mut LegacyNames) -> bool {
+    let Some(object) = engagement.as_object_mut() else {
+        return false;
+    };
+
+    let mut changed = false;
+    for (kind, seen) in [
+        ("instructor", &mut names.instructors),
+        ("host", &mut names.hosts),
+    ] {
+        if let Some(Value::String(name)) = object.remove(kind) {
+            object.insert(format!("{}_id", kind), json!(legacy_id(kind, &name)));
+            seen.insert(name);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Turns a roster of bare names into records. Engagements could name
+/// someone who had since been removed from the roster; they get an inactive
+/// record so nothing points at a missing id.
+fn upgrade_roster(roster: &mut Value, kind: &str, referenced: &BTreeSet<String>) -> bool {
+    let Some(items) = roster.as_array_mut() else {
+        return false;
+    };
+
+    let mut changed = false;
+    for item in items.iter_mut() {
+        if let Value::String(name) = item {
+            let name = name.clone();
+            *item = legacy_record(kind, &name, true);
+            changed = true;
+        }
+    }
+
+    let known: HashSet<String> = items
+        .iter()
+        .filter_map(|item| item.get("id").and_then(Value::as_str))
+        .map(str::to_string)
+        .collect();
+    for name in referenced {
+        if !known.contains(&legacy_id(kind, name).to_string()) {
+            items.push(legacy_record(kind, name, false));
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Upgrades engagements, trash and rosters laid out as in `BackupData` from
+/// instructor and host names to records referenced by id.
+pub fn upgrade_legacy_data(data: &mut Value) -> bool {
+    let mut names = LegacyNames::default();
+    let mut changed = false;
+    if let Some(engagements) = data.get_mut("engagements").and_then(Value::as_array_mut) {
+        for engagement in engagements {
+            changed |= upgrade_engagement(engagement, &mut names);
+        }
+    }
+    if let Some(trash) = data.get_mut("trash").and_then(Value::as_array_mut) {
+        for trashed in trash {
+            if let Some(engagement) = trashed.get_mut("engagement") {
+                changed |= upgrade_engagement(engagement, &mut names);
+            }
+        }
+    }
+    if let Some(instructors) = data.get_mut("instructors") {
+        changed |= upgrade_roster(instructors, "instructor", &names.instructors);
+    }
+    if let Some(hosts) = data.get_mut("hosts") {
+        changed |= upgrade_roster(hosts, "host", &names.hosts);
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_engagement(instructor: &str, host: &str) -> Value {
+        json!({
+            "id": Uuid::new_v4(),
+            "instructor": instructor,
+            "host": host,
+            "date": "2024-03-01",
+            "language": "English",
+            "title": "Intro",
+            "part": 1,
+            "num_parts": 1,
+            "status": "Planning"
+        })
+    }
+
+    fn names(roster: &Value) -> Vec<(String, bool)> {
+        roster
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| (r["name"].as_str().unwrap().to_string(), r["active"] == true))
+            .collect()
+    }
+
+    #[test]
+    fn legacy_names_become_records() {
+        let mut data = json!({
+            "engagements": [legacy_engagement("Ann Lee", "Hall")],
+            "trash": [{
+                "engagement": legacy_engagement("Bob Ray", "Hall"),
+                "deleted_at": "2024-03-02T09:30:00Z",
+                "deleted_by": "admin"
+            }],
+            "instructors": ["Ann Lee"],
+            "hosts": ["Hall"]
+        });
+        assert!(upgrade_legacy_data(&mut data));
+
+        let engagement = &data["engagements"][0];
+        assert!(engagement.get("instructor").is_none());
+        assert_eq!(
+            engagement["instructor_id"],
+            json!(legacy_id("instructor", "Ann Lee"))
+        );
+        assert_eq!(engagement["host_id"], json!(legacy_id("host", "Hall")));
+        assert_eq!(
+            data["trash"][0]["engagement"]["instructor_id"],
+            json!(legacy_id("instructor", "Bob Ray"))
+        );
+        // Bob was only named by an engagement, so gets an inactive record
+        assert_eq!(
+            names(&data["instructors"]),
+            vec![
+                ("Ann Lee".to_string(), true),
+                ("Bob Ray".to_string(), false)
+            ]
+        );
+        assert_eq!(names(&data["hosts"]), vec![("Hall".to_string(), true)]);
+
+        let upgraded = data.clone();
+        assert!(!upgrade_legacy_data(&mut data));
+        assert_eq!(data, upgraded);
+    }
+}
@@ -1,11 +1,18 @@
 use actix_web::web::ServiceConfig;
-use crate::{api::*, views::*, hosts::*, instructors::*};
+use crate::{account::*, api::*, audit::*, backups::*, calendar::*, csv_io::*, views::*, hosts::*, instructors::*, public::*, revisions::*, tokens::*, trash::*, users::*};
 
 pub fn config_eng_paths(cfg: &mut ServiceConfig) {
     cfg.service(add_eng);
     cfg.service(get_engs);
     cfg.service(edit_eng);
     cfg.service(delete_eng);
+    cfg.service(get_revisions);
+    cfg.service(revert_revision);
+}
+
+pub fn config_trash_paths(cfg: &mut ServiceConfig) {
+    cfg.service(get_trash);
+    cfg.service(restore_trashed);
 }
 
 pub fn config_view_paths(cfg: &mut ServiceConfig) {
@@ -14,16 +21,78 @@ pub fn config_view_paths(cfg: &mut ServiceConfig) {
     cfg.service(new_engagement_root);
     cfg.service(new_engagement);
     cfg.service(manage);
+    cfg.service(manage_trash_root);
+    cfg.service(manage_trash);
+    cfg.service(manage_backups);
+    cfg.service(manage_users);
+    cfg.service(manage_tokens);
+    cfg.service(import_export);
+    cfg.service(audit_log);
+    cfg.service(account);
 }
 
 pub fn config_ins_paths(cfg: &mut ServiceConfig) {
     cfg.service(add_instructor);
     cfg.service(get_instructors);
+    cfg.service(edit_instructor);
     cfg.service(delete_instructor);
 }
 
 pub fn config_hosts_paths(cfg: &mut ServiceConfig) {
     cfg.service(add_host);
     cfg.service(get_hosts);
+    cfg.service(edit_host);
     cfg.service(delete_host);
-}
\ No newline at end of file
+}
+
+pub fn config_backup_paths(cfg: &mut ServiceConfig) {
+    cfg.service(get_backups);
+    cfg.service(get_backup_status);
+    cfg.service(run_backup);
+    cfg.service(dry_run_restore);
+    cfg.service(restore_backup);
+}
+
+pub fn config_user_paths(cfg: &mut ServiceConfig) {
+    cfg.service(get_users);
+    cfg.service(add_user);
+    cfg.service(edit_user);
+    cfg.service(get_lockouts);
+    cfg.service(clear_lockout);
+    cfg.service(get_sessions);
+    cfg.service(revoke_session);
+}
+
+pub fn config_token_paths(cfg: &mut ServiceConfig) {
+    cfg.service(get_tokens);
+    cfg.service(add_token);
+    cfg.service(revoke_token);
+}
+
+pub fn config_audit_paths(cfg: &mut ServiceConfig) {
+    cfg.service(get_audit);
+}
+
+pub fn config_account_paths(cfg: &mut ServiceConfig) {
+    cfg.service(enroll_two_factor);
+    cfg.service(confirm_two_factor);
+    cfg.service(disable_two_factor);
+}
+
+pub fn config_csv_paths(cfg: &mut ServiceConfig) {
+    cfg.service(export_engagements);
+    cfg.service(export_language_engagements);
+    cfg.service(export_instructors);
+    cfg.service(export_hosts);
+    cfg.service(import_engagements);
+}
+
+pub fn config_calendar_paths(cfg: &mut ServiceConfig) {
+    cfg.service(calendar_feed);
+}
+
+/// Mounted under `/public`, outside the login requirement.
+pub fn config_public_paths(cfg: &mut ServiceConfig) {
+    cfg.service(public_engagements);
+    cfg.service(public_schedule);
+}
@@ -0,0 +1,515 @@
+use actix_session::storage::{
+    CookieSessionStore, LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::{time::Duration as CookieDuration, Key};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::store::{persist, StoreError};
+
+// Session state key under which actix-identity keeps the logged-in user id
+const IDENTITY_USER_KEY: &str = "actix_identity.user_id";
+// Rewritten by actix-identity on every request to enforce the visit deadline
+const IDENTITY_LAST_VISIT_KEY: &str = "actix_identity.last_visited_at";
+const SESSION_KEY_LEN: usize = 64;
+// A session that only saw another visit is written out at most this often
+const LAST_SEEN_WRITE_INTERVAL_SECS: i64 = 60;
+
+/// Session configuration read from the environment at startup.
+pub struct SessionSettings {
+    pub key: Key,
+    pub backend: SessionBackend,
+    /// Sessions end after this long without a request
+    pub idle_timeout: Option<std::time::Duration>,
+    /// Sessions end this long after login, however active they are
+    pub absolute_timeout: Option<std::time::Duration>,
+}
+
+impl SessionSettings {
+    pub fn from_env(data_dir: &Path) -> io::Result<Self> {
+        let key = match env::var("SESSION_KEY_FILE") {
+            Ok(path) => load_or_create_key(Path::new(&path))?,
+            Err(_) => {
+                log::warn!("SESSION_KEY_FILE is not set; sessions will not survive a restart");
+                Key::generate()
+            }
+        };
+
+        let backend = match env::var("SESSION_STORE").as_deref() {
+            Ok("file") => SessionBackend::File(
+                FileSessionStore::open(data_dir.join("sessions.json")).map_err(io::Error::other)?,
+            ),
+            _ => SessionBackend::Cookie,
+        };
+
+        Ok(Self {
+            key,
+            backend,
+            idle_timeout: timeout_from_env("SESSION_IDLE_TIMEOUT_MINUTES", 60)?,
+            absolute_timeout: timeout_from_env("SESSION_ABSOLUTE_TIMEOUT_HOURS", 60 * 60)?,
+        })
+    }
+}
+
+fn timeout_from_env(var: &str, unit_secs: u64) -> io::Result<Option<std::time::Duration>> {
+    match env::var(var) {
+        Ok(value) => value
+            .parse::<u64>()
+            .map(|n| Some(std::time::Duration::from_secs(n * unit_secs)))
+            .map_err(|e| io::Error::other(format!("{} must be a whole number: {}", var, e))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads the cookie signing key from `path`, generating and saving one on
+/// first start so every later start signs sessions with the same key.
+fn load_or_create_key(path: &Path) -> io::Result<Key> {
+    if path.exists() {
+        let encoded = fs::read_to_string(path)?;
+        let bytes = BASE64
+            .decode(encoded.trim())
+            .map_err(|e| io::Error::other(format!("{}: invalid base64: {}", path.display(), e)))?;
+        return Key::try_from(bytes.as_slice()).map_err(|e| {
+            io::Error::other(format!("{}: session key is too short: {}", path.display(), e))
+        });
+    }
+
+    let key = Key::generate();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    // Never readable by others, not even between creating and writing it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(BASE64.encode(key.master()).as_bytes())?;
+    file.sync_all()?;
+    log::info!("Generated a new session key at {}", path.display());
+    Ok(key)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+struct StoredSession {
+    state: HashMap<String, String>,
+    created_at: DateTime<Utc>,
+    /// Last request written to disk, so it trails the latest one by up to
+    /// `LAST_SEEN_WRITE_INTERVAL_SECS`
+    last_seen: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl StoredSession {
+    /// Whether `state` only differs from the stored state in the visit time,
+    /// and the session was written recently enough that it can wait.
+    fn can_defer(&self, state: &HashMap<String, String>, now: DateTime<Utc>) -> bool {
+        let same_state = self.state.len() == state.len()
+            && state.iter().all(|(k, v)| {
+                k == IDENTITY_LAST_VISIT_KEY || self.state.get(k) == Some(v)
+            });
+        same_state && now - self.last_seen < Duration::seconds(LAST_SEEN_WRITE_INTERVAL_SECS)
+    }
+}
+
+/// An active session as shown to admins. `id` is a digest of the session
+/// key, so listing sessions doesn't hand out anything that could be replayed.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct SessionSummary {
+    pub id: String,
+    pub username: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Keeps session state on the server, persisted to a JSON file, so sessions
+/// can be listed and revoked. Sessions are filed under a SHA-256 hash of
+/// their key, so the file can't be used to take one over. Clones share the
+/// same sessions.
+#[derive(Clone)]
+pub struct FileSessionStore {
+    sessions: Arc<Mutex<HashMap<String, StoredSession>>>,
+    path: Arc<PathBuf>,
+}
+
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn session_id(key_hash: &str) -> String {
+    key_hash[..16].to_string()
+}
+
+fn expiry(ttl: &CookieDuration) -> DateTime<Utc> {
+    Utc::now() + Duration::seconds(ttl.whole_seconds())
+}
+
+fn generate_key() -> String {
+    let mut bytes = [0u8; SESSION_KEY_LEN / 2];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl FileSessionStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let path = path.into();
+        let sessions = if path.exists() {
+            serde_json::from_reader(BufReader::new(File::open(&path)?))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            sessions: Arc::new(Mutex::new(sessions)),
+            path: Arc::new(path),
+        })
+    }
+
+    /// Applies `f` to the live sessions, dropping expired ones first, and
+    /// persists the result.
+    fn modify<R>(
+        &self,
+        f: impl FnOnce(&mut HashMap<String, StoredSession>) -> R,
+    ) -> Result<R, StoreError> {
+        let mut sessions = self.sessions.lock().map_err(|_| StoreError::Lock)?;
+        let mut next = sessions.clone();
+        let now = Utc::now();
+        next.retain(|_, s| s.expires_at > now);
+
+        let result = f(&mut next);
+        persist(&self.path, &next)?;
+        *sessions = next;
+        Ok(result)
+    }
+
+    /// Lists unexpired sessions, most recently active first.
+    pub fn list(&self) -> Result<Vec<SessionSummary>, StoreError> {
+        let now = Utc::now();
+        let sessions = self.sessions.lock().map_err(|_| StoreError::Lock)?;
+        let mut summaries: Vec<SessionSummary> = sessions
+            .iter()
+            .filter(|(_, s)| s.expires_at > now)
+            .map(|(key_hash, s)| SessionSummary {
+                id: session_id(key_hash),
+                username: s
+                    .state
+                    .get(IDENTITY_USER_KEY)
+                    .and_then(|raw| serde_json::from_str(raw).ok()),
+                created_at: s.created_at,
+                last_seen: s.last_seen,
+                expires_at: s.expires_at,
+            })
+            .collect();
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.last_seen));
+        Ok(summaries)
+    }
+
+    /// Ends the session whose summary id is `id`.
+    pub fn revoke(&self, id: &str) -> Result<bool, StoreError> {
+        self.modify(|sessions| {
+            let before = sessions.len();
+            sessions.retain(|key_hash, _| session_id(key_hash) != id);
+            sessions.len() < before
+        })
+    }
+
+    /// Records another visit to a session in memory only, if that's all that
+    /// changed and it was written recently. Returns false if it must be
+    /// written now.
+    fn defer_visit(
+        &self,
+        key_hash: &str,
+        state: Option<&HashMap<String, String>>,
+        ttl: &CookieDuration,
+    ) -> Result<bool, StoreError> {
+        let mut sessions = self.sessions.lock().map_err(|_| StoreError::Lock)?;
+        let now = Utc::now();
+        let Some(session) = sessions.get_mut(key_hash).filter(|s| s.expires_at > now) else {
+            return Ok(false);
+        };
+        if !session.can_defer(state.unwrap_or(&session.state), now) {
+            return Ok(false);
+        }
+
+        if let Some(state) = state {
+            session.state = state.clone();
+        }
+        session.expires_at = expiry(ttl);
+        Ok(true)
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let sessions = self
+            .sessions
+            .lock()
+            .map_err(|_| LoadError::Other(anyhow::anyhow!("Session store lock poisoned")))?;
+        Ok(sessions
+            .get(&hash_key(session_key.as_ref()))
+            .filter(|s| s.expires_at > Utc::now())
+            .map(|s| s.state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &CookieDuration,
+    ) -> Result<SessionKey, SaveError> {
+        let key = generate_key();
+        let now = Utc::now();
+        self.modify(|sessions| {
+            sessions.insert(
+                hash_key(&key),
+                StoredSession {
+                    state: session_state,
+                    created_at: now,
+                    last_seen: now,
+                    expires_at: expiry(ttl),
+                },
+            )
+        })
+        .map_err(|e| SaveError::Other(e.into()))?;
+
+        SessionKey::try_from(key).map_err(|e| SaveError::Other(e.into()))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &CookieDuration,
+    ) -> Result<SessionKey, UpdateError> {
+        let key_hash = hash_key(session_key.as_ref());
+        // Visits alone change the state on every request, so most of them
+        // don't need the whole file rewritten
+        if self
+            .defer_visit(&key_hash, Some(&session_state), ttl)
+            .map_err(|e| UpdateError::Other(e.into()))?
+        {
+            return Ok(session_key);
+        }
+
+        let found = self
+            .modify(|sessions| match sessions.get_mut(&key_hash) {
+                Some(session) => {
+                    session.state = session_state.clone();
+                    session.last_seen = Utc::now();
+                    session.expires_at = expiry(ttl);
+                    true
+                }
+                None => false,
+            })
+            .map_err(|e| UpdateError::Other(e.into()))?;
+
+        if found {
+            Ok(session_key)
+        } else {
+            // The session was revoked or expired meanwhile; start a new one
+            self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            })
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &CookieDuration,
+    ) -> Result<(), anyhow::Error> {
+        let key_hash = hash_key(session_key.as_ref());
+        if self.defer_visit(&key_hash, None, ttl)? {
+            return Ok(());
+        }
+
+        self.modify(|sessions| {
+            if let Some(session) = sessions.get_mut(&key_hash) {
+                session.last_seen = Utc::now();
+                session.expires_at = expiry(ttl);
+            }
+        })?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.modify(|sessions| sessions.remove(&hash_key(session_key.as_ref())))?;
+        Ok(())
+    }
+}
+
+/// The session store picked by `SESSION_STORE`: signed cookies by default,
+/// or the server-side file store.
+#[derive(Clone)]
+pub enum SessionBackend {
+    Cookie,
+    File(FileSessionStore),
+}
+
+impl SessionStore for SessionBackend {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        match self {
+            SessionBackend::Cookie => CookieSessionStore::default().load(session_key).await,
+            SessionBackend::File(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &CookieDuration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            SessionBackend::Cookie => CookieSessionStore::default().save(session_state, ttl).await,
+            SessionBackend::File(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &CookieDuration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            SessionBackend::Cookie => {
+                CookieSessionStore::default()
+                    .update(session_key, session_state, ttl)
+                    .await
+            }
+            SessionBackend::File(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &CookieDuration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            SessionBackend::Cookie => CookieSessionStore::default().update_ttl(session_key, ttl).await,
+            SessionBackend::File(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            SessionBackend::Cookie => CookieSessionStore::default().delete(session_key).await,
+            SessionBackend::File(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("{}-{}", name, Uuid::new_v4()))
+    }
+
+    fn state(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn only_key_hashes_are_written() {
+        let path = temp_path("sessions.json");
+        let store = FileSessionStore::open(&path).unwrap();
+        let ttl = CookieDuration::minutes(30);
+        let key = store
+            .save(state(&[(IDENTITY_USER_KEY, "\"ann\"")]), &ttl)
+            .await
+            .unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(!written.contains(key.as_ref()));
+        assert!(written.contains(&hash_key(key.as_ref())));
+
+        let reopened = FileSessionStore::open(&path).unwrap();
+        let loaded = reopened.load(&key).await.unwrap().unwrap();
+        assert_eq!(loaded[IDENTITY_USER_KEY], "\"ann\"");
+
+        let summaries = reopened.list().unwrap();
+        assert_eq!(summaries[0].username.as_deref(), Some("ann"));
+        assert!(reopened.revoke(&summaries[0].id).unwrap());
+        assert!(reopened.load(&key).await.unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn visits_alone_are_not_written_every_time() {
+        let path = temp_path("sessions.json");
+        let store = FileSessionStore::open(&path).unwrap();
+        let ttl = CookieDuration::minutes(30);
+        let key = store
+            .save(
+                state(&[
+                    (IDENTITY_USER_KEY, "\"ann\""),
+                    (IDENTITY_LAST_VISIT_KEY, "1"),
+                ]),
+                &ttl,
+            )
+            .await
+            .unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+
+        let visited = state(&[
+            (IDENTITY_USER_KEY, "\"ann\""),
+            (IDENTITY_LAST_VISIT_KEY, "2"),
+        ]);
+        let key = store.update(key, visited.clone(), &ttl).await.unwrap();
+        store.update_ttl(&key, &ttl).await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+        assert_eq!(store.load(&key).await.unwrap(), Some(visited));
+
+        // Anything else in the state is written straight away
+        let changed = state(&[
+            (IDENTITY_USER_KEY, "\"ann\""),
+            (IDENTITY_LAST_VISIT_KEY, "3"),
+            ("csrf_token", "\"ab12\""),
+        ]);
+        let key = store.update(key, changed.clone(), &ttl).await.unwrap();
+        let reopened = FileSessionStore::open(&path).unwrap();
+        assert_eq!(reopened.load(&key).await.unwrap(), Some(changed));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn session_key_file_is_private_and_reused() {
+        let path = temp_path("session.key");
+        let key = load_or_create_key(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(load_or_create_key(&path).unwrap().master(), key.master());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    hash::Hash,
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::api::{Engagement, TrashedEngagement};
+use crate::hosts::Host;
+use crate::instructors::Instructor;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("Storage I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Storage serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("Failed to acquire store lock")]
+    Lock,
+}
+
+impl actix_web::ResponseError for StoreError {
+    fn error_response(&self) -> actix_web::HttpResponse {
+        log::error!("Store operation failed: {}", self);
+        actix_web::HttpResponse::InternalServerError().body("Failed to access data store")
+    }
+}
+
+/// Outcome of replacing an engagement that is expected to be at a given version.
+#[derive(Debug)]
+pub enum VersionedUpdate {
+    /// The engagement as stored, under its new version
+    Updated(Engagement),
+    /// The engagement changed since the expected version; holds the stored copy
+    Conflict(Engagement),
+    NotFound,
+}
+
+/// Outcome of removing an engagement that is expected to be at a given version.
+#[derive(Debug)]
+pub enum VersionedRemove {
+    Removed(Engagement),
+    /// The engagement changed since the expected version; holds the stored copy
+    Conflict(Engagement),
+    NotFound,
+}
+
+pub trait EngagementStore: Send + Sync {
+    fn list(&self) -> Result<Vec<Engagement>, StoreError>;
+    fn insert(&self, engagement: Engagement) -> Result<(), StoreError>;
+    /// Replaces an existing engagement if it is still at `expected_version`,
+    /// storing the replacement under the next version.
+    fn update(
+        &self,
+        engagement: Engagement,
+        expected_version: u64,
+    ) -> Result<VersionedUpdate, StoreError>;
+    /// Removes an engagement if it is still at `expected_version`.
+    fn remove(&self, id: Uuid, expected_version: u64) -> Result<VersionedRemove, StoreError>;
+    fn replace_all(&self, engagements: HashSet<Engagement>) -> Result<(), StoreError>;
+}
+
+pub trait TrashStore: Send + Sync {
+    fn list(&self) -> Result<Vec<TrashedEngagement>, StoreError>;
+    fn insert(&self, trashed: TrashedEngagement) -> Result<(), StoreError>;
+    /// Takes an engagement out of the trash, returning it if it was there.
+    fn remove(&self, id: Uuid) -> Result<Option<TrashedEngagement>, StoreError>;
+    fn replace_all(&self, trash: HashSet<TrashedEngagement>) -> Result<(), StoreError>;
+}
+
+pub trait InstructorStore: Send + Sync {
+    fn list(&self) -> Result<Vec<Instructor>, StoreError>;
+    fn insert(&self, instructor: Instructor) -> Result<(), StoreError>;
+    /// Replaces an existing instructor, returning false if its id is unknown.
+    fn update(&self, instructor: Instructor) -> Result<bool, StoreError>;
+    fn remove(&self, id: Uuid) -> Result<Option<Instructor>, StoreError>;
+    fn replace_all(&self, instructors: HashSet<Instructor>) -> Result<(), StoreError>;
+}
+
+pub trait HostStore: Send + Sync {
+    fn list(&self) -> Result<Vec<Host>, StoreError>;
+    fn insert(&self, host: Host) -> Result<(), StoreError>;
+    /// Replaces an existing host, returning false if its id is unknown.
+    fn update(&self, host: Host) -> Result<bool, StoreError>;
+    fn remove(&self, id: Uuid) -> Result<Option<Host>, StoreError>;
+    fn replace_all(&self, hosts: HashSet<Host>) -> Result<(), StoreError>;
+}
+
+/// A set of records, optionally mirrored to a JSON file that is rewritten
+/// atomically before any mutation becomes visible.
+struct Table<T> {
+    items: Mutex<HashSet<T>>,
+    path: Option<PathBuf>,
+}
+
+impl<T> Table<T>
+where
+    T: Clone + Eq + Hash + Serialize + DeserializeOwned,
+{
+    fn in_memory() -> Self {
+        Self {
+            items: Mutex::new(HashSet::new()),
+            path: None,
+        }
+    }
+
+    fn open(path: PathBuf) -> Result<Self, StoreError> {
+        let items = if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            serde_json::from_reader(reader)?
+        } else {
+            HashSet::new()
+        };
+
+        Ok(Self {
+            items: Mutex::new(items),
+            path: Some(path),
+        })
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&HashSet<T>) -> R) -> Result<R, StoreError> {
+        let items = self.items.lock().map_err(|_| StoreError::Lock)?;
+        Ok(f(&items))
+    }
+
+    fn write<R>(&self, f: impl FnOnce(&mut HashSet<T>) -> R) -> Result<R, StoreError> {
+        let mut items = self.items.lock().map_err(|_| StoreError::Lock)?;
+        let mut next = items.clone();
+        let result = f(&mut next);
+
+        if let Some(path) = &self.path {
+            persist(path, &next)?;
+        }
+
+        *items = next;
+        Ok(result)
+    }
+}
+
+pub fn persist<T: Serialize>(path: &Path, data: &T) -> Result<(), StoreError> {
+    let tmp_path = path.with_extension("json.tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, data)?;
+        file.flush()?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Local storage for engagements, the trash and the instructor/host rosters,
+/// either held purely in memory or persisted under a data directory.
+pub struct LocalStore {
+    engagements: Table<Engagement>,
+    trash: Table<TrashedEngagement>,
+    instructors: Table<Instructor>,
+    hosts: Table<Host>,
+}
+
+impl LocalStore {
+    pub fn in_memory() -> Self {
+        Self {
+            engagements: Table::in_memory(),
+            trash: Table::in_memory(),
+            instructors: Table::in_memory(),
+            hosts: Table::in_memory(),
+        }
+    }
+
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            engagements: Table::open(dir.join("engagements.json"))?,
+            trash: Table::open(dir.join("trash.json"))?,
+            instructors: Table::open(dir.join("instructors.json"))?,
+            hosts: Table::open(dir.join("hosts.json"))?,
+        })
+    }
+}
+
+impl EngagementStore for LocalStore {
+    fn list(&self) -> Result<Vec<Engagement>, StoreError> {
+        self.engagements.read(|items| items.iter().cloned().collect())
+    }
+
+    fn insert(&self, engagement: Engagement) -> Result<(), StoreError> {
+        self.engagements.write(|items| {
+            items.replace(engagement);
+        })
+    }
+
+    fn update(
+        &self,
+        engagement: Engagement,
+        expected_version: u64,
+    ) -> Result<VersionedUpdate, StoreError> {
+        self.engagements.write(|items| match items.get(&engagement) {
+            None => VersionedUpdate::NotFound,
+            Some(stored) if stored.version != expected_version => {
+                VersionedUpdate::Conflict(stored.clone())
+            }
+            Some(_) => {
+                let updated = Engagement {
+                    version: expected_version + 1,
+                    ..engagement
+                };
+                items.replace(updated.clone());
+                VersionedUpdate::Updated(updated)
+            }
+        })
+    }
+
+    fn remove(&self, id: Uuid, expected_version: u64) -> Result<VersionedRemove, StoreError> {
+        self.engagements.write(|items| {
+            match items.iter().find(|e| e.id == id).cloned() {
+                None => VersionedRemove::NotFound,
+                Some(stored) if stored.version != expected_version => {
+                    VersionedRemove::Conflict(stored)
+                }
+                Some(stored) => {
+                    items.remove(&stored);
+                    VersionedRemove::Removed(stored)
+                }
+            }
+        })
+    }
+
+    fn replace_all(&self, engagements: HashSet<Engagement>) -> Result<(), StoreError> {
+        self.engagements.write(|items| *items = engagements)
+    }
+}
+
+impl TrashStore for LocalStore {
+    fn list(&self) -> Result<Vec<TrashedEngagement>, StoreError> {
+        self.trash.read(|items| items.iter().cloned().collect())
+    }
+
+    fn insert(&self, trashed: TrashedEngagement) -> Result<(), StoreError> {
+        self.trash.write(|items| {
+            items.replace(trashed);
+        })
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<TrashedEngagement>, StoreError> {
+        self.trash.write(|items| {
+            let existing = items.iter().find(|t| t.engagement.id == id).cloned();
+            existing.and_then(|t| items.take(&t))
+        })
+    }
+
+    fn replace_all(&self, trash: HashSet<TrashedEngagement>) -> Result<(), StoreError> {
+        self.trash.write(|items| *items = trash)
+    }
+}
+
+impl InstructorStore for LocalStore {
+    fn list(&self) -> Result<Vec<Instructor>, StoreError> {
+        self.instructors.read(|items| items.iter().cloned().collect())
+    }
+
+    fn insert(&self, instructor: Instructor) -> Result<(), StoreError> {
+        self.instructors.write(|items| {
+            items.replace(instructor);
+        })
+    }
+
+    fn update(&self, instructor: Instructor) -> Result<bool, StoreError> {
+        self.instructors.write(|items| {
+            if items.contains(&instructor) {
+                items.replace(instructor);
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<Instructor>, StoreError> {
+        self.instructors.write(|items| {
+            let existing = items.iter().find(|i| i.id == id).cloned();
+            existing.and_then(|i| items.take(&i))
+        })
+    }
+
+    fn replace_all(&self, instructors: HashSet<Instructor>) -> Result<(), StoreError> {
+        self.instructors.write(|items| *items = instructors)
+    }
+}
+
+impl HostStore for LocalStore {
+    fn list(&self) -> Result<Vec<Host>, StoreError> {
+        self.hosts.read(|items| items.iter().cloned().collect())
+    }
+
+    fn insert(&self, host: Host) -> Result<(), StoreError> {
+        self.hosts.write(|items| {
+            items.replace(host);
+        })
+    }
+
+    fn update(&self, host: Host) -> Result<bool, StoreError> {
+        self.hosts.write(|items| {
+            if items.contains(&host) {
+                items.replace(host);
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<Host>, StoreError> {
+        self.hosts.write(|items| {
+            let existing = items.iter().find(|h| h.id == id).cloned();
+            existing.and_then(|h| items.take(&h))
+        })
+    }
+
+    fn replace_all(&self, hosts: HashSet<Host>) -> Result<(), StoreError> {
+        self.hosts.write(|items| *items = hosts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Language, NewEngagement, Status};
+
+    fn stored(store: &LocalStore) -> Engagement {
+        let engagement = NewEngagement {
+            instructor_id: Uuid::new_v4(),
+            host_id: Uuid::new_v4(),
+            date: "2024-05-01".to_string(),
+            language: Language::English,
+            title: "Intro".to_string(),
+            part: 1,
+            num_parts: 1,
+            status: Status::Planning,
+        }
+        .into_engagement();
+        EngagementStore::insert(store, engagement.clone()).unwrap();
+        engagement
+    }
+
+    #[test]
+    fn updates_need_the_stored_version() {
+        let store = LocalStore::in_memory();
+        let engagement = stored(&store);
+        let edited = Engagement {
+            title: "Edited".to_string(),
+            ..engagement.clone()
+        };
+
+        let VersionedUpdate::Updated(updated) =
+            EngagementStore::update(&store, edited.clone(), 1).unwrap()
+        else {
+            panic!("update at the stored version was refused");
+        };
+        assert_eq!(updated.version, 2);
+        assert!(matches!(
+            EngagementStore::update(&store, edited, 1).unwrap(),
+            VersionedUpdate::Conflict(current) if current.version == 2
+        ));
+        assert!(matches!(
+            EngagementStore::update(&store, stored(&LocalStore::in_memory()), 1).unwrap(),
+            VersionedUpdate::NotFound
+        ));
+    }
+
+    #[test]
+    fn removals_need_the_stored_version() {
+        let store = LocalStore::in_memory();
+        let engagement = stored(&store);
+
+        assert!(matches!(
+            EngagementStore::remove(&store, engagement.id, 2).unwrap(),
+            VersionedRemove::Conflict(_)
+        ));
+        assert_eq!(EngagementStore::list(&store).unwrap().len(), 1);
+        assert!(matches!(
+            EngagementStore::remove(&store, engagement.id, 1).unwrap(),
+            VersionedRemove::Removed(_)
+        ));
+        assert!(matches!(
+            EngagementStore::remove(&store, engagement.id, 1).unwrap(),
+            VersionedRemove::NotFound
+        ));
+    }
+}
@@ -0,0 +1,114 @@
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use crate::api::Language;
+use crate::api_tokens::{self, ApiToken, ApiTokenStore, TokenScope};
+use crate::auth::{CurrentUser, Role};
+
+/// A token as listed to admins, without its hash.
+#[derive(serde::Serialize)]
+pub struct TokenSummary {
+    pub id: String,
+    pub name: String,
+    pub scope: TokenScope,
+    pub languages: Vec<Language>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl From<ApiToken> for TokenSummary {
+    fn from(token: ApiToken) -> Self {
+        Self {
+            id: token.id,
+            name: token.name,
+            scope: token.scope,
+            languages: token.languages,
+            created_by: token.created_by,
+            created_at: token.created_at,
+            last_used: token.last_used,
+        }
+    }
+}
+
+fn default_scope() -> TokenScope {
+    TokenScope::ReadOnly
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewToken {
+    pub name: String,
+    #[serde(default = "default_scope")]
+    pub scope: TokenScope,
+    #[serde(default)]
+    pub languages: Vec<Language>,
+}
+
+#[get("/tokens")]
+pub async fn get_tokens(
+    tokens: Data<ApiTokenStore>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let tokens: Vec<TokenSummary> = tokens.list()?.into_iter().map(TokenSummary::from).collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(tokens))
+}
+
+#[post("/tokens")]
+pub async fn add_token(
+    tokens: Data<ApiTokenStore>,
+    body: Json<NewToken>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let body = body.into_inner();
+    match api_tokens::create_token(
+        &tokens,
+        &body.name,
+        body.scope,
+        body.languages,
+        &user.username,
+    ) {
+        Ok((record, token)) => {
+            log::info!("{} created API token '{}'", user.username, record.name);
+            Ok(HttpResponse::Created()
+                .content_type("application/json; charset=utf-8")
+                .json(json!({
+                    "token": token,
+                    "record": TokenSummary::from(record)
+                })))
+        }
+        Err(e) => Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .json(json!({
+                "error": "Validation failed",
+                "details": e
+            }))),
+    }
+}
+
+#[delete("/tokens/{id}")]
+pub async fn revoke_token(
+    tokens: Data<ApiTokenStore>,
+    id: Path<String>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    if api_tokens::revoke_token(&tokens, &id)? {
+        log::info!("{} revoked API token {}", user.username, *id);
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
@@ -0,0 +1,270 @@
+use actix_web::{
+    get, post,
+    web::{Data, Path},
+    HttpResponse,
+};
+use chrono::{Duration, Utc};
+use std::{env, io, sync::Arc};
+use uuid::Uuid;
+
+use crate::api::{Language, TrashedEngagement};
+use crate::audit::{AuditAction, AuditLog, AuditRecord};
+use crate::auth::{CurrentUser, Role};
+use crate::revisions::RevisionStore;
+use crate::store::{StoreError, VersionedRemove};
+use crate::types::{EngagementRepo, TrashRepo};
+
+// How often the trash is checked for engagements past their retention
+const PURGE_INTERVAL_SECS: u64 = 3600;
+
+pub struct TrashSettings {
+    /// Days a deleted engagement stays restorable before it is purged
+    pub retention_days: i64,
+}
+
+impl TrashSettings {
+    pub fn from_env() -> io::Result<Self> {
+        let retention_days = match env::var("TRASH_RETENTION_DAYS") {
+            Ok(value) => value.parse().map_err(|e| {
+                io::Error::other(format!(
+                    "TRASH_RETENTION_DAYS must be a whole number: {}",
+                    e
+                ))
+            })?,
+            Err(_) => 30,
+        };
+
+        Ok(Self { retention_days })
+    }
+}
+
+/// Moves an engagement to the trash on behalf of `user`, as long as it is
+/// still at `expected_version`. It is added to the trash before it leaves the
+/// schedule, so a failure in between can't lose it.
+pub fn trash_engagement(
+    repo: &EngagementRepo,
+    trash: &TrashRepo,
+    id: Uuid,
+    expected_version: u64,
+    user: &str,
+) -> Result<VersionedRemove, StoreError> {
+    let Some(engagement) = repo.list()?.into_iter().find(|e| e.id == id) else {
+        return Ok(VersionedRemove::NotFound);
+    };
+    if engagement.version != expected_version {
+        return Ok(VersionedRemove::Conflict(engagement));
+    }
+
+    trash.insert(TrashedEngagement {
+        engagement,
+        deleted_at: Utc::now(),
+        deleted_by: user.to_string(),
+    })?;
+    let outcome = repo.remove(id, expected_version)?;
+    if !matches!(outcome, VersionedRemove::Removed(_)) {
+        // It was edited or deleted since it was read, so it stays where it is
+        trash.remove(id)?;
+    }
+    Ok(outcome)
+}
+
+/// Permanently removes engagements that have been in the trash for longer
+/// than `retention_days`, returning how many were purged.
+pub fn purge_expired(
+    trash: &TrashRepo,
+    revisions: &RevisionStore,
+    audit: &AuditLog,
+    retention_days: i64,
+) -> Result<usize, StoreError> {
+    let cutoff = Utc::now() - Duration::days(retention_days);
+    let mut purged = 0;
+
+    for trashed in trash.list()? {
+        if trashed.deleted_at >= cutoff {
+            continue;
+        }
+        if let Some(removed) = trash.remove(trashed.engagement.id)? {
+            revisions.forget(removed.engagement.id)?;
+            audit.record(
+                AuditRecord::new(
+                    "system",
+                    AuditAction::PurgeEngagement,
+                    removed.engagement.id,
+                )
+                .before(&removed),
+            )?;
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
+
+pub fn start_purge_task(
+    trash: TrashRepo,
+    revisions: Data<RevisionStore>,
+    audit: Arc<AuditLog>,
+    settings: Data<TrashSettings>,
+) {
+    log::info!(
+        "Starting trash purge task with retention of {} days",
+        settings.retention_days
+    );
+
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(PURGE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match purge_expired(&trash, &revisions, &audit, settings.retention_days) {
+                Ok(0) => {}
+                Ok(purged) => log::info!("Purged {} engagements from the trash", purged),
+                Err(e) => log::error!("Trash purge failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Trashed engagements in one language, most recently deleted first.
+pub fn trashed_in(
+    trash: &TrashRepo,
+    lang: &Language,
+) -> Result<Vec<TrashedEngagement>, StoreError> {
+    let mut trashed: Vec<TrashedEngagement> = trash
+        .list()?
+        .into_iter()
+        .filter(|t| t.engagement.language == *lang)
+        .collect();
+    trashed.sort_by_key(|t| std::cmp::Reverse(t.deleted_at));
+    Ok(trashed)
+}
+
+#[get("/trash/{lang}")]
+pub async fn get_trash(
+    trash: Data<TrashRepo>,
+    lang: Path<Language>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+    user.require_language(&lang)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(trashed_in(&trash, &lang)?))
+}
+
+#[post("/trash/{id}/restore")]
+pub async fn restore_trashed(
+    repo: Data<EngagementRepo>,
+    trash: Data<TrashRepo>,
+    audit: Data<AuditLog>,
+    id: Path<Uuid>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let Some(trashed) = trash.list()?.into_iter().find(|t| t.engagement.id == *id) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    user.require_language(&trashed.engagement.language)?;
+
+    // Back on the schedule first, so a failure can't lose the engagement
+    let engagement = trashed.engagement;
+    repo.insert(engagement.clone())?;
+    trash.remove(engagement.id)?;
+    audit.record(
+        AuditRecord::new(
+            &user.username,
+            AuditAction::RestoreEngagement,
+            engagement.id,
+        )
+        .after(&engagement),
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(engagement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Engagement, NewEngagement, Status};
+    use crate::store::LocalStore;
+
+    fn repos() -> (EngagementRepo, TrashRepo, Engagement) {
+        let store = Arc::new(LocalStore::in_memory());
+        let repo = EngagementRepo::new(store.clone());
+        let engagement = NewEngagement {
+            instructor_id: Uuid::new_v4(),
+            host_id: Uuid::new_v4(),
+            date: "2024-05-01".to_string(),
+            language: Language::English,
+            title: "Intro".to_string(),
+            part: 1,
+            num_parts: 1,
+            status: Status::Planning,
+        }
+        .into_engagement();
+        repo.insert(engagement.clone()).unwrap();
+        (repo, TrashRepo::new(store), engagement)
+    }
+
+    #[test]
+    fn stale_deletes_leave_the_engagement_in_place() {
+        let (repo, trash, engagement) = repos();
+
+        let outcome = trash_engagement(&repo, &trash, engagement.id, 2, "ann").unwrap();
+        assert!(matches!(outcome, VersionedRemove::Conflict(_)));
+        assert_eq!(repo.list().unwrap(), vec![engagement.clone()]);
+        assert!(trash.list().unwrap().is_empty());
+
+        let outcome = trash_engagement(&repo, &trash, Uuid::new_v4(), 1, "ann").unwrap();
+        assert!(matches!(outcome, VersionedRemove::NotFound));
+    }
+
+    #[test]
+    fn deletes_move_the_engagement_to_the_trash() {
+        let (repo, trash, engagement) = repos();
+
+        let outcome = trash_engagement(&repo, &trash, engagement.id, 1, "ann").unwrap();
+        assert!(matches!(outcome, VersionedRemove::Removed(_)));
+        assert!(repo.list().unwrap().is_empty());
+        let trashed = trash.list().unwrap();
+        assert_eq!(trashed[0].engagement, engagement);
+        assert_eq!(trashed[0].deleted_by, "ann");
+    }
+
+    #[test]
+    fn purging_removes_only_expired_engagements() {
+        let (repo, trash, engagement) = repos();
+        let dir = std::env::temp_dir().join(format!("trash-test-{}", Uuid::new_v4()));
+        let revisions = RevisionStore::open(dir.join("revisions.json")).unwrap();
+        let audit = AuditLog::open(dir.join("audit.jsonl")).unwrap();
+        revisions.record_created(&engagement, "ann").unwrap();
+        trash_engagement(&repo, &trash, engagement.id, 1, "ann").unwrap();
+
+        let recent = TrashedEngagement {
+            engagement: Engagement {
+                id: Uuid::new_v4(),
+                ..engagement.clone()
+            },
+            deleted_at: Utc::now() - Duration::days(29),
+            deleted_by: "ann".to_string(),
+        };
+        let expired = TrashedEngagement {
+            deleted_at: Utc::now() - Duration::days(31),
+            ..trash.list().unwrap().remove(0)
+        };
+        trash.insert(recent.clone()).unwrap();
+        trash.insert(expired).unwrap();
+
+        assert_eq!(purge_expired(&trash, &revisions, &audit, 30).unwrap(), 1);
+        assert_eq!(trash.list().unwrap(), vec![recent]);
+        assert!(revisions.history(engagement.id).unwrap().is_empty());
+        let purges = audit.entries().unwrap();
+        assert_eq!(purges[0].action, AuditAction::PurgeEngagement);
+        assert_eq!(purges[0].target, engagement.id.to_string());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use qrcode::{render::svg, QrCode};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::auth::UserStore;
+
+const ISSUER: &str = "Admin Panel";
+const DIGITS: usize = 6;
+const STEP_SECS: u64 = 30;
+// Codes from one step either side of now are accepted to allow for clock drift
+const SKEW_STEPS: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// A user's TOTP (RFC 6238) second factor.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct TwoFactor {
+    /// Base32 secret, set once enrollment has been confirmed with a code
+    pub secret: Option<String>,
+    /// Secret handed out by `begin_enrollment` and awaiting its first code
+    pub pending_secret: Option<String>,
+    /// SHA-256 hashes of the recovery codes that haven't been used yet
+    pub recovery_codes: Vec<String>,
+    /// Time step of the last accepted code, so a code can't be replayed
+    pub last_used_step: Option<u64>,
+}
+
+impl TwoFactor {
+    pub fn enabled(&self) -> bool {
+        self.secret.is_some()
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Enrollment {
+    pub secret: String,
+    pub otpauth_url: String,
+    pub qr_svg: String,
+}
+
+fn build_totp(secret: &str, username: &str) -> Result<TOTP, String> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| format!("Invalid TOTP secret: {:?}", e))?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        SKEW_STEPS as u8,
+        STEP_SECS,
+        bytes,
+        Some(ISSUER.to_string()),
+        username.to_string(),
+    )
+    .map_err(|e| format!("Invalid TOTP parameters: {}", e))
+}
+
+/// Returns the time step `code` is valid for, if it matches any step within
+/// the allowed skew of now.
+fn matching_step(totp: &TOTP, code: &str) -> Option<u64> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let current = now / STEP_SECS;
+
+    (current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS)
+        .find(|step| totp.generate(step * STEP_SECS) == code)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 6];
+            OsRng.fill_bytes(&mut bytes);
+            let code: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{}-{}-{}", &code[0..4], &code[4..8], &code[8..12])
+        })
+        .collect()
+}
+
+/// Starts enrollment by generating a new secret for `username`. 2FA isn't
+/// enforced until the secret is confirmed with `confirm_enrollment`.
+pub fn begin_enrollment(users: &UserStore, username: &str) -> Result<Enrollment, String> {
+    let secret = Secret::generate_secret().to_encoded().to_string();
+    let totp = build_totp(&secret, username)?;
+    let otpauth_url = totp.get_url();
+    let qr_svg = QrCode::new(otpauth_url.as_bytes())
+        .map_err(|e| format!("Failed to render QR code: {}", e))?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    users
+        .modify(|users| match users.get_mut(username) {
+            Some(user) if user.two_factor.enabled() => {
+                Err("Two-factor authentication is already enabled".to_string())
+            }
+            Some(user) => {
+                user.two_factor.pending_secret = Some(secret.clone());
+                Ok(())
+            }
+            None => Err(format!("User '{}' not found", username)),
+        })
+        .map_err(|e| e.to_string())??;
+
+    Ok(Enrollment {
+        secret,
+        otpauth_url,
+        qr_svg,
+    })
+}
+
+/// Activates the pending secret if `code` is valid for it, returning the
+/// user's new recovery codes. They are only ever shown this once.
+pub fn confirm_enrollment(
+    users: &UserStore,
+    username: &str,
+    code: &str,
+) -> Result<Vec<String>, String> {
+    let recovery_codes = generate_recovery_codes();
+
+    users
+        .modify(|users| {
+            let user = users
+                .get_mut(username)
+                .ok_or_else(|| format!("User '{}' not found", username))?;
+            let secret = user
+                .two_factor
+                .pending_secret
+                .clone()
+                .ok_or_else(|| "No two-factor enrollment is in progress".to_string())?;
+            let step = matching_step(&build_totp(&secret, username)?, code.trim())
+                .ok_or_else(|| "Invalid authentication code".to_string())?;
+
+            user.two_factor = TwoFactor {
+                secret: Some(secret),
+                pending_secret: None,
+                recovery_codes: recovery_codes.iter().map(|c| hash_recovery_code(c)).collect(),
+                last_used_step: Some(step),
+            };
+            Ok(())
+        })
+        .map_err(|e| e.to_string())??;
+
+    Ok(recovery_codes)
+}
+
+/// Checks a login's second factor, accepting either a current TOTP code or
+/// an unused recovery code. Accepted codes are used up.
+pub fn verify(users: &UserStore, username: &str, code: &str) -> Result<bool, String> {
+    let code = code.trim();
+
+    users
+        .modify(|users| {
+            let Some(user) = users.get_mut(username) else {
+                return Ok(false);
+            };
+            let Some(secret) = user.two_factor.secret.clone() else {
+                return Ok(false);
+            };
+
+            if let Some(step) = matching_step(&build_totp(&secret, username)?, code) {
+                if user.two_factor.last_used_step.is_some_and(|last| step <= last) {
+                    return Ok(false);
+                }
+                user.two_factor.last_used_step = Some(step);
+                return Ok(true);
+            }
+
+            let hashed = hash_recovery_code(code);
+            let before = user.two_factor.recovery_codes.len();
+            user.two_factor.recovery_codes.retain(|c| *c != hashed);
+            Ok(user.two_factor.recovery_codes.len() < before)
+        })
+        .map_err(|e| e.to_string())?
+}
+
+/// Removes a user's second factor, e.g. when they've lost their device.
+pub fn disable(users: &UserStore, username: &str) -> Result<(), String> {
+    users
+        .modify(|users| match users.get_mut(username) {
+            Some(user) => {
+                user.two_factor = TwoFactor::default();
+                Ok(())
+            }
+            None => Err(format!("User '{}' not found", username)),
+        })
+        .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{self, Role};
+
+    fn enrolled() -> (UserStore, std::path::PathBuf, TOTP, Vec<String>) {
+        let path = std::env::temp_dir().join(format!("2fa-test-{}.json", uuid::Uuid::new_v4()));
+        let users = UserStore::open(&path).unwrap();
+        auth::create_user(&users, "ann", "ann-password", Role::Admin, Vec::new()).unwrap();
+
+        let enrollment = begin_enrollment(&users, "ann").unwrap();
+        let totp = build_totp(&enrollment.secret, "ann").unwrap();
+        assert!(confirm_enrollment(&users, "ann", "000000x").is_err());
+        let codes = confirm_enrollment(&users, "ann", &totp.generate_current().unwrap()).unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        (users, path, totp, codes)
+    }
+
+    fn code_at(totp: &TOTP, step: u64) -> String {
+        totp.generate(step * STEP_SECS)
+    }
+
+    #[test]
+    fn codes_cannot_be_replayed() {
+        let (users, path, totp, _) = enrolled();
+        let step = users
+            .get("ann")
+            .unwrap()
+            .unwrap()
+            .two_factor
+            .last_used_step
+            .unwrap();
+
+        // The code that confirmed enrollment is already used up
+        assert!(!verify(&users, "ann", &code_at(&totp, step)).unwrap());
+        assert!(verify(&users, "ann", &code_at(&totp, step + 1)).unwrap());
+        assert!(!verify(&users, "ann", &code_at(&totp, step + 1)).unwrap());
+        // Nor can an older code still inside the skew window be used
+        assert!(!verify(&users, "ann", &code_at(&totp, step)).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn recovery_codes_work_once() {
+        let (users, path, _, codes) = enrolled();
+        let code = codes[0].to_uppercase().replace('-', " ");
+
+        assert!(verify(&users, "ann", &code).unwrap());
+        assert!(!verify(&users, "ann", &codes[0]).unwrap());
+        assert!(verify(&users, "ann", &codes[1]).unwrap());
+        assert_eq!(
+            users
+                .get("ann")
+                .unwrap()
+                .unwrap()
+                .two_factor
+                .recovery_codes
+                .len(),
+            RECOVERY_CODE_COUNT - 2
+        );
+
+        disable(&users, "ann").unwrap();
+        assert!(!users.get("ann").unwrap().unwrap().two_factor.enabled());
+        assert!(!verify(&users, "ann", &codes[2]).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+}
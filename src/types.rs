@@ -1,27 +1,71 @@
-use std::{collections::HashSet, sync::{Arc, Mutex}};
+use std::{ops::Deref, sync::Arc};
+
+use crate::store::{EngagementStore, HostStore, InstructorStore, TrashStore};
+
+#[derive(Clone)]
+pub struct EngagementRepo(pub Arc<dyn EngagementStore>);
 
 #[derive(Clone)]
-pub struct InstructorRepo(pub Arc<Mutex<HashSet<String>>>);
+pub struct TrashRepo(pub Arc<dyn TrashStore>);
 
 #[derive(Clone)]
-pub struct HostRepo(pub Arc<Mutex<HashSet<String>>>);
+pub struct InstructorRepo(pub Arc<dyn InstructorStore>);
+
+#[derive(Clone)]
+pub struct HostRepo(pub Arc<dyn HostStore>);
+
+impl EngagementRepo {
+    pub fn new(store: Arc<dyn EngagementStore>) -> Self {
+        Self(store)
+    }
+}
+
+impl Deref for EngagementRepo {
+    type Target = dyn EngagementStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl TrashRepo {
+    pub fn new(store: Arc<dyn TrashStore>) -> Self {
+        Self(store)
+    }
+}
+
+impl Deref for TrashRepo {
+    type Target = dyn TrashStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
 
 impl InstructorRepo {
-    pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(HashSet::new())))
+    pub fn new(store: Arc<dyn InstructorStore>) -> Self {
+        Self(store)
     }
+}
+
+impl Deref for InstructorRepo {
+    type Target = dyn InstructorStore;
 
-    pub fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashSet<String>>, std::sync::PoisonError<std::sync::MutexGuard<'_, HashSet<String>>>> {
-        self.0.lock()
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
     }
 }
 
 impl HostRepo {
-    pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(HashSet::new())))
+    pub fn new(store: Arc<dyn HostStore>) -> Self {
+        Self(store)
     }
+}
+
+impl Deref for HostRepo {
+    type Target = dyn HostStore;
 
-    pub fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashSet<String>>, std::sync::PoisonError<std::sync::MutexGuard<'_, HashSet<String>>>> {
-        self.0.lock()
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,295 @@
+use actix_web::{
+    delete, get, patch, post,
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use crate::api::Language;
+use crate::auth::{self, CurrentUser, Role, User, UserStore};
+use crate::login_throttle::{LoginThrottle, ThrottleKey};
+use crate::session_store::FileSessionStore;
+use crate::two_factor::TwoFactor;
+
+#[derive(serde::Serialize)]
+pub struct UserSummary {
+    pub username: String,
+    pub disabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub role: Role,
+    pub languages: Vec<Language>,
+    pub two_factor_enabled: bool,
+}
+
+impl From<User> for UserSummary {
+    fn from(user: User) -> Self {
+        Self {
+            username: user.username,
+            disabled: user.disabled,
+            created_at: user.created_at,
+            role: user.role,
+            two_factor_enabled: user.two_factor.enabled(),
+            languages: user.languages,
+        }
+    }
+}
+
+fn default_role() -> Role {
+    Role::Viewer
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    #[serde(default = "default_role")]
+    pub role: Role,
+    #[serde(default)]
+    pub languages: Vec<Language>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UserUpdate {
+    pub disabled: Option<bool>,
+    pub password: Option<String>,
+    pub role: Option<Role>,
+    pub languages: Option<Vec<Language>>,
+    /// Removes the user's second factor, e.g. after they lose their device
+    #[serde(default)]
+    pub reset_two_factor: bool,
+}
+
+fn bad_request(details: String) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Validation failed",
+            "details": details
+        }))
+}
+
+#[get("/users")]
+pub async fn get_users(
+    users: Data<UserStore>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let users: Vec<UserSummary> = users.list()?.into_iter().map(UserSummary::from).collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(users))
+}
+
+#[post("/users")]
+pub async fn add_user(
+    users: Data<UserStore>,
+    body: Json<NewUser>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let body = body.into_inner();
+    match auth::create_user(
+        &users,
+        &body.username,
+        &body.password,
+        body.role,
+        body.languages,
+    ) {
+        Ok(()) => Ok(HttpResponse::Created().finish()),
+        Err(e) => Ok(bad_request(e)),
+    }
+}
+
+#[patch("/users/{username}")]
+pub async fn edit_user(
+    users: Data<UserStore>,
+    username: Path<String>,
+    body: Json<UserUpdate>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    if users.get(&username)?.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    // Admins can't lock themselves out; another admin has to do it
+    let is_self = user.username == *username;
+    if is_self && body.disabled == Some(true) {
+        return Ok(bad_request("You cannot disable your own account".to_string()));
+    }
+    if is_self && body.role.is_some_and(|role| role < Role::Admin) {
+        return Ok(bad_request("You cannot remove your own admin role".to_string()));
+    }
+
+    // Everything that can be rejected is checked first, and the changes are
+    // then saved together, so a bad field never leaves the others half-applied
+    let password_hash = match body.password.as_deref().map(auth::hash_password).transpose() {
+        Ok(hash) => hash,
+        Err(e) => return Ok(bad_request(e)),
+    };
+
+    let update = body.into_inner();
+    let reset_two_factor = update.reset_two_factor;
+    let result = users.modify(|users| match users.get_mut(username.as_str()) {
+        Some(account) => {
+            if let Some(disabled) = update.disabled {
+                account.disabled = disabled;
+            }
+            if let Some(role) = update.role {
+                account.role = role;
+            }
+            if let Some(languages) = update.languages {
+                account.languages = languages;
+            }
+            if reset_two_factor {
+                account.two_factor = TwoFactor::default();
+            }
+            if let Some(password_hash) = password_hash {
+                account.password_hash = password_hash;
+            }
+            Ok(())
+        }
+        None => Err(format!("User '{}' not found", *username)),
+    })?;
+    if let Err(e) = result {
+        return Ok(bad_request(e));
+    }
+
+    if reset_two_factor {
+        log::info!("{} reset two-factor authentication for {}", user.username, *username);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/lockouts")]
+pub async fn get_lockouts(
+    throttle: Data<LoginThrottle>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(throttle.list()?))
+}
+
+#[delete("/lockouts")]
+pub async fn clear_lockout(
+    throttle: Data<LoginThrottle>,
+    body: Json<ThrottleKey>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    if throttle.clear(&body)? {
+        log::info!("{} cleared login lockout {:?}", user.username, *body);
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+fn sessions_unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .content_type("application/json")
+        .json(json!({
+            "error": "Sessions unavailable",
+            "details": "Sessions are only tracked with SESSION_STORE=file"
+        }))
+}
+
+#[get("/sessions")]
+pub async fn get_sessions(
+    sessions: Option<Data<FileSessionStore>>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let Some(sessions) = sessions else {
+        return Ok(sessions_unavailable());
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .json(sessions.list()?))
+}
+
+#[delete("/sessions/{id}")]
+pub async fn revoke_session(
+    sessions: Option<Data<FileSessionStore>>,
+    id: Path<String>,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let Some(sessions) = sessions else {
+        return Ok(sessions_unavailable());
+    };
+
+    if sessions.revoke(&id)? {
+        log::info!("{} revoked session {}", user.username, *id);
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        dev::Service,
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        App, HttpMessage,
+    };
+
+    #[actix_web::test]
+    async fn edits_are_all_or_nothing() {
+        let path = std::env::temp_dir().join(format!("users-test-{}.json", uuid::Uuid::new_v4()));
+        let users = Data::new(UserStore::open(&path).unwrap());
+        auth::create_user(&users, "admin", "admin-password", Role::Admin, Vec::new()).unwrap();
+        auth::create_user(&users, "ann", "ann-password", Role::Viewer, Vec::new()).unwrap();
+        let admin = users.get("admin").unwrap().unwrap();
+
+        let app = init_service(
+            App::new()
+                .app_data(users.clone())
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(admin.clone());
+                    srv.call(req)
+                })
+                .service(edit_user),
+        )
+        .await;
+        let edit = |body| TestRequest::patch().uri("/users/ann").set_json(body).to_request();
+
+        // A password that's too short rejects the role change with it
+        let response = call_service(
+            &app,
+            edit(json!({"role": "Scheduler", "languages": ["English"], "password": "x"})),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let ann = users.get("ann").unwrap().unwrap();
+        assert_eq!(ann.role, Role::Viewer);
+        assert!(ann.languages.is_empty());
+
+        let response = call_service(
+            &app,
+            edit(json!({"role": "Scheduler", "languages": ["English"], "disabled": true})),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let ann = users.get("ann").unwrap().unwrap();
+        assert_eq!(ann.role, Role::Scheduler);
+        assert_eq!(ann.languages, vec![Language::English]);
+        assert!(ann.disabled);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,66 +1,333 @@
-use actix_identity::Identity;
 use actix_web::{
     get,
-    web::{Data, Path},
+    web::{Data, Path, Query},
     HttpResponse,
 };
 use askama_actix::Template;
-use std::{
-    collections::HashSet,
-    sync::{Arc, Mutex},
-};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashSet;
+use uuid::Uuid;
 
 use crate::api::{compare_engagement_dates, Engagement, Language};
+use crate::api_tokens::{ApiTokenStore, TokenScope};
+use crate::audit::{self, AuditAction, AuditLog, AuditQuery};
+use crate::auth::{AuthSettings, CurrentUser, Role, User, UserStore};
+use crate::backup::{BackupMetrics, BackupPageQuery, BackupSystem};
+use crate::csrf::CsrfToken;
+use crate::hosts::Host;
+use crate::instructors::Instructor;
+use crate::login_throttle::{LoginThrottle, ThrottleKey};
+use crate::roster::{Roster, RosterNames};
+use crate::session_store::FileSessionStore;
+use crate::trash::{self, TrashSettings};
 use crate::types::*;
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct EngagementTemplate {
+    csrf_token: String,
     engagements: Vec<Engagement>,
     engagements_json: Vec<String>,
     lang: String,
     all_langs: Vec<String>,
     has_language: bool,
-    unique_instructors: Vec<String>,
-    unique_hosts: Vec<String>,
+    names: RosterNames,
+    /// Everyone on the rosters, for the edit form
+    instructors: Vec<Instructor>,
+    hosts: Vec<Host>,
+    /// Id and name of everyone on this language's engagements, for the filters
+    unique_instructors: Vec<(String, String)>,
+    unique_hosts: Vec<(String, String)>,
+    can_schedule: bool,
+    is_admin: bool,
 }
 
 #[derive(Template)]
 #[template(path = "new.html")]
 struct NewEngagementTemplate {
+    csrf_token: String,
     lang: String,
     all_langs: Vec<String>,
     has_language: bool,
-    instructors: Vec<String>,
-    hosts: Vec<String>,
+    /// Active instructors and hosts for the language
+    instructors: Vec<Instructor>,
+    hosts: Vec<Host>,
+    is_admin: bool,
 }
 
 #[derive(Template)]
 #[template(path = "manage.html")]
 struct ManageTemplate {
-    instructors: Vec<String>,
-    hosts: Vec<String>,
+    csrf_token: String,
+    instructors: Vec<Instructor>,
+    instructors_json: Vec<String>,
+    hosts: Vec<Host>,
+    hosts_json: Vec<String>,
+    all_langs: Vec<String>,
+    is_admin: bool,
+}
+
+struct TrashRow {
+    id: String,
+    title: String,
+    instructor: String,
+    host: String,
+    date: String,
+    part: usize,
+    num_parts: usize,
+    deleted_at: String,
+    deleted_by: String,
+    purge_at: String,
+}
+
+#[derive(Template)]
+#[template(path = "trash.html")]
+struct TrashTemplate {
+    csrf_token: String,
+    lang: String,
+    all_langs: Vec<String>,
+    has_language: bool,
+    rows: Vec<TrashRow>,
+    retention_days: i64,
+    is_admin: bool,
+}
+
+struct BackupRow {
+    name: String,
+    last_modified: String,
+    size: i64,
+    message_count: String,
+    instructor_count: String,
+    host_count: String,
+}
+
+#[derive(Template)]
+#[template(path = "backups.html")]
+struct BackupsTemplate {
+    csrf_token: String,
+    configured: bool,
+    last_success: String,
+    last_failure: String,
+    last_error: Option<String>,
+    next_run: String,
+    metrics: Option<BackupMetrics>,
+    backups: Vec<BackupRow>,
+    list_error: Option<String>,
+    /// Where the previous and next pages of the listing start, if there are any
+    previous_offset: Option<usize>,
+    next_offset: Option<usize>,
+    total: usize,
+}
+
+struct UserRow {
+    username: String,
+    disabled: bool,
+    created_at: String,
+    role: String,
+    languages: Vec<String>,
+    two_factor_enabled: bool,
+}
+
+#[derive(Template)]
+#[template(path = "account.html")]
+struct AccountTemplate {
+    csrf_token: String,
+    username: String,
+    role: String,
+    can_schedule: bool,
+    is_admin: bool,
+    two_factor_enabled: bool,
+    recovery_codes_left: usize,
+    enrollment_required: bool,
+}
+
+struct SessionRow {
+    id: String,
+    username: String,
+    created_at: String,
+    last_seen: String,
+    expires_at: String,
+}
+
+struct LockoutRow {
+    kind: String,
+    value: String,
+    key_json: String,
+    failures: u32,
+    last_failure: String,
+    locked_until: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "users.html")]
+struct UsersTemplate {
+    csrf_token: String,
+    users: Vec<UserRow>,
+    current_user: String,
+    roles: Vec<String>,
+    languages: Vec<String>,
+    lockouts: Vec<LockoutRow>,
+    sessions_tracked: bool,
+    sessions: Vec<SessionRow>,
+}
+
+#[derive(Template)]
+#[template(path = "import.html")]
+struct ImportTemplate {
+    csrf_token: String,
+    languages: Vec<String>,
+    is_admin: bool,
+}
+
+struct TokenRow {
+    id: String,
+    name: String,
+    scope: String,
+    languages: Vec<String>,
+    created_by: String,
+    created_at: String,
+    last_used: String,
+}
+
+#[derive(Template)]
+#[template(path = "tokens.html")]
+struct TokensTemplate {
+    csrf_token: String,
+    tokens: Vec<TokenRow>,
+    scopes: Vec<String>,
+    languages: Vec<String>,
+}
+
+struct FieldChange {
+    field: String,
+    before: String,
+    after: String,
+}
+
+struct AuditRow {
+    timestamp: String,
+    user: String,
+    action: String,
+    target: String,
+    changes: Vec<FieldChange>,
+}
+
+#[derive(Template)]
+#[template(path = "audit.html")]
+struct AuditTemplate {
+    csrf_token: String,
+    rows: Vec<AuditRow>,
+    matching: usize,
+    users: Vec<String>,
+    actions: Vec<String>,
+    selected_user: String,
+    selected_action: String,
+    search: String,
+}
+
+// Older changes are still searchable, they just aren't all listed at once
+const AUDIT_PAGE_SIZE: usize = 500;
+
+/// Language tabs shown to `user`, limited to the languages they can access.
+fn visible_languages(user: &User) -> Vec<String> {
+    Language::ALL
+        .iter()
+        .filter(|lang| user.can_access(lang))
+        .map(Language::to_string)
+        .collect()
+}
+
+fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "Never".to_string())
+}
+
+/// Flattens a before/after snapshot into displayable fields. Roster and
+/// backup records hold a single value, which gets an empty field name.
+fn audit_fields(value: &Option<serde_json::Value>) -> Vec<(String, String)> {
+    let display = |v: &serde_json::Value| match v {
+        serde_json::Value::String(s) => s.clone(),
+        v => v.to_string(),
+    };
+    match value {
+        Some(serde_json::Value::Object(fields)) => fields
+            .iter()
+            .map(|(field, v)| (field.clone(), display(v)))
+            .collect(),
+        Some(v) => vec![(String::new(), display(v))],
+        None => Vec::new(),
+    }
+}
+
+/// The fields that differ between a record's before and after snapshots.
+fn field_changes(
+    before: &Option<serde_json::Value>,
+    after: &Option<serde_json::Value>,
+) -> Vec<FieldChange> {
+    let before = audit_fields(before);
+    let after = audit_fields(after);
+    let lookup = |fields: &[(String, String)], name: &str| {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    };
+
+    let mut names: Vec<&String> = after.iter().map(|(field, _)| field).collect();
+    for (field, _) in &before {
+        if !names.contains(&field) {
+            names.push(field);
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| FieldChange {
+            field: name.clone(),
+            before: lookup(&before, name),
+            after: lookup(&after, name),
+        })
+        .filter(|change| change.before != change.after)
+        .collect()
+}
+
+/// Serializes a record for a `data-` attribute. The template escapes it like
+/// any other text, which is what keeps free-text fields from breaking out of
+/// the attribute.
+fn attribute_json<T: serde::Serialize>(record: &T) -> String {
+    serde_json::to_string(record).unwrap_or_default()
+}
+
+fn format_count(count: Option<usize>) -> String {
+    count
+        .map(|c| c.to_string())
+        .unwrap_or_else(|| "-".to_string())
 }
 
 #[get("/views/index")]
-pub async fn index_root(_user: Identity) -> Result<HttpResponse, actix_web::Error> {
-    let all_langs = vec![
-        "English".to_string(),
-        "Spanish".to_string(),
-        "French".to_string(),
-        "Italian".to_string(),
-        "Portuguese".to_string(),
-        "German".to_string(),
-    ];
+pub async fn index_root(
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Viewer)?;
+
+    let all_langs = visible_languages(&user);
 
     let template = EngagementTemplate {
+        csrf_token: csrf.0,
         engagements: Vec::new(),
         engagements_json: Vec::new(),
         lang: String::new(),
         all_langs,
         has_language: false,
+        names: RosterNames::default(),
+        instructors: Vec::new(),
+        hosts: Vec::new(),
         unique_instructors: Vec::new(),
         unique_hosts: Vec::new(),
+        can_schedule: user.role >= Role::Scheduler,
+        is_admin: user.role >= Role::Admin,
     };
 
     Ok(HttpResponse::Ok()
@@ -74,107 +341,107 @@ pub async fn index_root(_user: Identity) -> Result<HttpResponse, actix_web::Erro
 
 #[get("/views/index/{lang}")]
 pub async fn index(
-    repo: Data<Arc<Mutex<HashSet<Engagement>>>>,
+    repo: Data<EngagementRepo>,
+    roster: Data<Roster>,
     lang: Path<Language>,
-    _user: Identity,
+    csrf: CsrfToken,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let all_langs = vec![
-        "English".to_string(),
-        "Spanish".to_string(),
-        "French".to_string(),
-        "Italian".to_string(),
-        "Portuguese".to_string(),
-        "German".to_string(),
-    ];
-
-    match repo.lock() {
-        Ok(repo) => {
-            let mut engagements: Vec<Engagement> = repo
-                .iter()
-                .filter(|x| x.language == *lang)
-                .cloned()
-                .collect();
-
-            engagements.sort_by(|a, b| match compare_engagement_dates(a, b) {
-                Ok(ordering) => ordering,
-                Err(_) => {
-                    log::error!(
-                        "Failed to compare dates between engagements '{}' ({}) and '{}' ({})",
-                        a.instructor,
-                        a.date,
-                        b.instructor,
-                        b.date
-                    );
-                    a.date.cmp(&b.date)
-                }
-            });
-
-            let engagements_json: Vec<String> = engagements
-                .iter()
-                .map(|eng| {
-                    serde_json::to_string(eng)
-                        .unwrap_or_default()
-                        .replace('\'', "\\'")
-                })
-                .collect();
-
-            let mut unique_instructors: Vec<String> = engagements
-                .iter()
-                .map(|e| e.instructor.clone())
-                .collect::<HashSet<String>>()
-                .into_iter()
-                .collect();
-            unique_instructors.sort();
-
-            let mut unique_hosts: Vec<String> = engagements
-                .iter()
-                .map(|e| e.host.clone())
-                .collect::<HashSet<String>>()
-                .into_iter()
-                .collect();
-            unique_hosts.sort();
-
-            let template = EngagementTemplate {
-                engagements,
-                engagements_json,
-                lang: format!("{:?}", *lang),
-                all_langs,
-                has_language: true,
-                unique_instructors,
-                unique_hosts,
-            };
-
-            Ok(HttpResponse::Ok()
-                .content_type("text/html; charset=utf-8")
-                .body(
-                    template
-                        .render()
-                        .map_err(actix_web::error::ErrorInternalServerError)?,
-                ))
+    user.require(Role::Viewer)?;
+    user.require_language(&lang)?;
+
+    let all_langs = visible_languages(&user);
+
+    let mut engagements: Vec<Engagement> = repo
+        .list()?
+        .into_iter()
+        .filter(|x| x.language == *lang)
+        .collect();
+
+    engagements.sort_by(|a, b| match compare_engagement_dates(a, b) {
+        Ok(ordering) => ordering,
+        Err(_) => {
+            log::error!(
+                "Failed to compare dates between engagements '{}' ({}) and '{}' ({})",
+                a.title,
+                a.date,
+                b.title,
+                b.date
+            );
+            a.date.cmp(&b.date)
         }
-        Err(_) => Err(actix_web::error::ErrorInternalServerError(
-            "Failed to acquire repo lock",
-        )),
-    }
+    });
+
+    let engagements_json: Vec<String> = engagements.iter().map(attribute_json).collect();
+
+    let names = roster.names()?;
+
+    let mut unique_instructors: Vec<(String, String)> = engagements
+        .iter()
+        .map(|e| e.instructor_id)
+        .collect::<HashSet<Uuid>>()
+        .into_iter()
+        .map(|id| (id.to_string(), names.instructor(&id).to_string()))
+        .collect();
+    unique_instructors.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut unique_hosts: Vec<(String, String)> = engagements
+        .iter()
+        .map(|e| e.host_id)
+        .collect::<HashSet<Uuid>>()
+        .into_iter()
+        .map(|id| (id.to_string(), names.host(&id).to_string()))
+        .collect();
+    unique_hosts.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut instructors: Vec<Instructor> = roster.instructors.list()?;
+    instructors.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut hosts: Vec<Host> = roster.hosts.list()?;
+    hosts.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let template = EngagementTemplate {
+        csrf_token: csrf.0,
+        engagements,
+        engagements_json,
+        lang: format!("{:?}", *lang),
+        all_langs,
+        has_language: true,
+        names,
+        instructors,
+        hosts,
+        unique_instructors,
+        unique_hosts,
+        can_schedule: user.role >= Role::Scheduler,
+        is_admin: user.role >= Role::Admin,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
 }
 
 #[get("/views/new")]
-pub async fn new_engagement_root(_user: Identity) -> Result<HttpResponse, actix_web::Error> {
-    let all_langs = vec![
-        "English".to_string(),
-        "Spanish".to_string(),
-        "French".to_string(),
-        "Italian".to_string(),
-        "Portuguese".to_string(),
-        "German".to_string(),
-    ];
+pub async fn new_engagement_root(
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let all_langs = visible_languages(&user);
 
     let template = NewEngagementTemplate {
+        csrf_token: csrf.0,
         lang: String::new(),
         all_langs,
         has_language: false,
         instructors: Vec::new(),
         hosts: Vec::new(),
+        is_admin: user.role >= Role::Admin,
     };
 
     Ok(HttpResponse::Ok()
@@ -191,49 +458,36 @@ pub async fn new_engagement(
     lang: Path<Language>,
     instructor_repo: Data<InstructorRepo>,
     host_repo: Data<HostRepo>,
-    _user: Identity,
+    csrf: CsrfToken,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let all_langs = vec![
-        "English".to_string(),
-        "Spanish".to_string(),
-        "French".to_string(),
-        "Italian".to_string(),
-        "Portuguese".to_string(),
-        "German".to_string(),
-    ];
-
-    let instructors = match instructor_repo.lock() {
-        Ok(repo) => {
-            let mut instructors: Vec<String> = repo.iter().cloned().collect();
-            instructors.sort();
-            instructors
-        }
-        Err(_) => {
-            return Err(actix_web::error::ErrorInternalServerError(
-                "Failed to acquire instructor repo lock",
-            ))
-        }
-    };
+    user.require(Role::Scheduler)?;
+    user.require_language(&lang)?;
 
-    let hosts = match host_repo.lock() {
-        Ok(repo) => {
-            let mut hosts: Vec<String> = repo.iter().cloned().collect();
-            hosts.sort();
-            hosts
-        }
-        Err(_) => {
-            return Err(actix_web::error::ErrorInternalServerError(
-                "Failed to acquire host repo lock",
-            ))
-        }
-    };
+    let all_langs = visible_languages(&user);
+
+    let mut instructors: Vec<Instructor> = instructor_repo
+        .list()?
+        .into_iter()
+        .filter(|i| i.available_for(&lang))
+        .collect();
+    instructors.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut hosts: Vec<Host> = host_repo
+        .list()?
+        .into_iter()
+        .filter(|h| h.available_for(&lang))
+        .collect();
+    hosts.sort_by(|a, b| a.name.cmp(&b.name));
 
     let template = NewEngagementTemplate {
+        csrf_token: csrf.0,
         lang: format!("{:?}", *lang),
         all_langs,
         has_language: true,
         instructors,
         hosts,
+        is_admin: user.role >= Role::Admin,
     };
 
     Ok(HttpResponse::Ok()
@@ -249,38 +503,390 @@ pub async fn new_engagement(
 pub async fn manage(
     instructor_repo: Data<InstructorRepo>,
     host_repo: Data<HostRepo>,
-    _user: Identity,
+    csrf: CsrfToken,
+    user: CurrentUser,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let instructors = match instructor_repo.lock() {
-        Ok(repo) => {
-            let mut instructors: Vec<String> = repo.iter().cloned().collect();
-            instructors.sort();
-            println!("Instructors: {:?}", instructors);
-            instructors
-        }
-        Err(_) => {
-            return Err(actix_web::error::ErrorInternalServerError(
-                "Failed to acquire instructor repo lock",
-            ))
-        }
-    };
+    user.require(Role::Scheduler)?;
+
+    let mut instructors: Vec<Instructor> = instructor_repo.list()?;
+    instructors.sort_by(|a, b| a.name.cmp(&b.name));
 
     // Get hosts from host repo
-    let hosts = match host_repo.lock() {
-        Ok(repo) => {
-            let mut hosts: Vec<String> = repo.iter().cloned().collect();
-            hosts.sort();
-            println!("Hosts: {:?}", hosts);
-            hosts
-        }
-        Err(_) => {
-            return Err(actix_web::error::ErrorInternalServerError(
-                "Failed to acquire host repo lock",
-            ))
+    let mut hosts: Vec<Host> = host_repo.list()?;
+    hosts.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let template = ManageTemplate {
+        csrf_token: csrf.0,
+        instructors_json: instructors.iter().map(attribute_json).collect(),
+        instructors,
+        hosts_json: hosts.iter().map(attribute_json).collect(),
+        hosts,
+        all_langs: Language::ALL.iter().map(Language::to_string).collect(),
+        is_admin: user.role >= Role::Admin,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[get("/views/trash")]
+pub async fn manage_trash_root(
+    settings: Data<TrashSettings>,
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let template = TrashTemplate {
+        csrf_token: csrf.0,
+        lang: String::new(),
+        all_langs: visible_languages(&user),
+        has_language: false,
+        rows: Vec::new(),
+        retention_days: settings.retention_days,
+        is_admin: user.role >= Role::Admin,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[get("/views/trash/{lang}")]
+pub async fn manage_trash(
+    trash_repo: Data<TrashRepo>,
+    roster: Data<Roster>,
+    settings: Data<TrashSettings>,
+    lang: Path<Language>,
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+    user.require_language(&lang)?;
+
+    let names = roster.names()?;
+    let rows = trash::trashed_in(&trash_repo, &lang)?
+        .into_iter()
+        .map(|t| TrashRow {
+            id: t.engagement.id.to_string(),
+            title: t.engagement.title,
+            instructor: names.instructor(&t.engagement.instructor_id).to_string(),
+            host: names.host(&t.engagement.host_id).to_string(),
+            date: t.engagement.date,
+            part: t.engagement.part,
+            num_parts: t.engagement.num_parts,
+            deleted_at: format_time(Some(t.deleted_at)),
+            deleted_by: t.deleted_by,
+            purge_at: format_time(Some(t.deleted_at + Duration::days(settings.retention_days))),
+        })
+        .collect();
+
+    let template = TrashTemplate {
+        csrf_token: csrf.0,
+        lang: format!("{:?}", *lang),
+        all_langs: visible_languages(&user),
+        has_language: true,
+        rows,
+        retention_days: settings.retention_days,
+        is_admin: user.role >= Role::Admin,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[get("/views/backups")]
+pub async fn manage_backups(
+    backup_system: Option<Data<BackupSystem>>,
+    query: Query<BackupPageQuery>,
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let template = match backup_system {
+        Some(backup_system) => {
+            let status = backup_system.status();
+            let limit = query.limit();
+            let (backups, list_error, total) =
+                match backup_system.list_backups(query.offset, limit).await {
+                    Ok(page) => (
+                        page.backups
+                            .into_iter()
+                            .map(|b| BackupRow {
+                                name: b.name,
+                                last_modified: format_time(b.last_modified),
+                                size: b.size,
+                                message_count: format_count(b.message_count),
+                                instructor_count: format_count(b.instructor_count),
+                                host_count: format_count(b.host_count),
+                            })
+                            .collect(),
+                        None,
+                        page.total,
+                    ),
+                    Err(e) => (Vec::new(), Some(e.to_string()), 0),
+                };
+
+            BackupsTemplate {
+                csrf_token: csrf.0,
+                configured: true,
+                last_success: format_time(status.last_success),
+                last_failure: format_time(status.last_failure),
+                last_error: status.last_error,
+                next_run: status
+                    .next_run
+                    .map(|t| format_time(Some(t)))
+                    .unwrap_or_else(|| "Not scheduled".to_string()),
+                metrics: status.last_metrics,
+                backups,
+                list_error,
+                previous_offset: (query.offset > 0).then(|| query.offset.saturating_sub(limit)),
+                next_offset: Some(query.offset + limit).filter(|&next| next < total),
+                total,
+            }
         }
+        None => BackupsTemplate {
+            csrf_token: csrf.0,
+            configured: false,
+            last_success: String::new(),
+            last_failure: String::new(),
+            last_error: None,
+            next_run: String::new(),
+            metrics: None,
+            backups: Vec::new(),
+            list_error: None,
+            previous_offset: None,
+            next_offset: None,
+            total: 0,
+        },
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[get("/views/users")]
+pub async fn manage_users(
+    users: Data<UserStore>,
+    throttle: Data<LoginThrottle>,
+    session_store: Option<Data<FileSessionStore>>,
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let sessions = match &session_store {
+        Some(store) => store
+            .list()?
+            .into_iter()
+            .map(|s| SessionRow {
+                id: s.id,
+                username: s.username.unwrap_or_else(|| "-".to_string()),
+                created_at: format_time(Some(s.created_at)),
+                last_seen: format_time(Some(s.last_seen)),
+                expires_at: format_time(Some(s.expires_at)),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let template = UsersTemplate {
+        csrf_token: csrf.0,
+        users: users
+            .list()?
+            .into_iter()
+            .map(|u| UserRow {
+                username: u.username,
+                disabled: u.disabled,
+                created_at: format_time(Some(u.created_at)),
+                role: u.role.to_string(),
+                languages: u.languages.iter().map(Language::to_string).collect(),
+                two_factor_enabled: u.two_factor.enabled(),
+            })
+            .collect(),
+        current_user: user.username.clone(),
+        roles: [Role::Viewer, Role::Scheduler, Role::Admin]
+            .iter()
+            .map(Role::to_string)
+            .collect(),
+        languages: Language::ALL.iter().map(Language::to_string).collect(),
+        lockouts: throttle
+            .list()?
+            .into_iter()
+            .map(|l| {
+                let (kind, value) = match &l.key {
+                    ThrottleKey::User(username) => ("User", username.clone()),
+                    ThrottleKey::Ip(ip) => ("IP", ip.clone()),
+                };
+                LockoutRow {
+                    kind: kind.to_string(),
+                    value,
+                    key_json: serde_json::to_string(&l.key).unwrap_or_default(),
+                    failures: l.failures,
+                    last_failure: format_time(Some(l.last_failure)),
+                    locked_until: l.locked_until.map(|t| format_time(Some(t))),
+                }
+            })
+            .collect(),
+        sessions_tracked: session_store.is_some(),
+        sessions,
     };
 
-    let template = ManageTemplate { instructors, hosts };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[get("/views/import")]
+pub async fn import_export(
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Scheduler)?;
+
+    let template = ImportTemplate {
+        csrf_token: csrf.0,
+        languages: visible_languages(&user),
+        is_admin: user.role >= Role::Admin,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[get("/views/audit")]
+pub async fn audit_log(
+    log: Data<AuditLog>,
+    query: Query<AuditQuery>,
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let mut users: Vec<String> = log
+        .entries()?
+        .into_iter()
+        .map(|r| r.user)
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
+    users.sort();
+
+    let records = audit::search(&log, &query)?;
+    let template = AuditTemplate {
+        csrf_token: csrf.0,
+        matching: records.len(),
+        rows: records
+            .into_iter()
+            .take(AUDIT_PAGE_SIZE)
+            .map(|r| AuditRow {
+                timestamp: format_time(Some(r.timestamp)),
+                changes: field_changes(&r.before, &r.after),
+                user: r.user,
+                action: r.action.to_string(),
+                target: r.target,
+            })
+            .collect(),
+        users,
+        actions: AuditAction::ALL.iter().map(AuditAction::to_string).collect(),
+        selected_user: query.user.clone().unwrap_or_default(),
+        selected_action: query.action.map(|a| a.to_string()).unwrap_or_default(),
+        search: query.q.clone().unwrap_or_default(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[get("/views/tokens")]
+pub async fn manage_tokens(
+    tokens: Data<ApiTokenStore>,
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require(Role::Admin)?;
+
+    let template = TokensTemplate {
+        csrf_token: csrf.0,
+        tokens: tokens
+            .list()?
+            .into_iter()
+            .map(|t| TokenRow {
+                id: t.id,
+                name: t.name,
+                scope: t.scope.to_string(),
+                languages: t.languages.iter().map(Language::to_string).collect(),
+                created_by: t.created_by,
+                created_at: format_time(Some(t.created_at)),
+                last_used: format_time(t.last_used),
+            })
+            .collect(),
+        scopes: TokenScope::ALL.iter().map(TokenScope::to_string).collect(),
+        languages: Language::ALL.iter().map(Language::to_string).collect(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            template
+                .render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[get("/views/account")]
+pub async fn account(
+    settings: Data<AuthSettings>,
+    csrf: CsrfToken,
+    user: CurrentUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    let two_factor_enabled = user.two_factor.enabled();
+    let template = AccountTemplate {
+        csrf_token: csrf.0,
+        username: user.username.clone(),
+        role: user.role.to_string(),
+        can_schedule: user.role >= Role::Scheduler,
+        is_admin: user.role >= Role::Admin,
+        two_factor_enabled,
+        recovery_codes_left: user.two_factor.recovery_codes.len(),
+        enrollment_required: settings.require_admin_2fa
+            && user.role >= Role::Admin
+            && !two_factor_enabled,
+    };
 
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")